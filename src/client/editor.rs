@@ -90,9 +90,23 @@ impl Editor {
                 Key::Char('\n') => {
                     self.newline = true;
                     history.add(line.clone());
-                    match Request::send(&line, self.proc_lst.clone()) {
+                    let mut streamed = false;
+                    let res = Request::send_with_progress(line, self.proc_lst.clone(), |progress| {
+                        print!("\n\r{}", progress);
+                        stdout().flush().unwrap();
+                        streamed = true;
+                    });
+                    if streamed {
+                        // already on screen, above the progress
+                        line.clear();
+                    }
+                    match res {
                         Ok(res) => match res.cmd {
                             CallOn::Status => line.push_str(self.get_status(res).as_str()),
+                            CallOn::Details(_) => match res.success {
+                                Ok(details) => line.push_str(&format!("\n\r{}", details.replace('\n', "\n\r"))),
+                                Err(failed) => line.push_str(&format!("\n\r{}", failed)),
+                            },
                             _ => match res.success {
                                 Ok(success) => line.push_str(&format!("\n\r{}", success)),
                                 Err(failed) => line.push_str(&format!("\n\r{}", failed)),
@@ -100,7 +114,14 @@ impl Editor {
                         },
                         Err(e) => line.push_str(format!("\n\r\t{}", e).as_str()),
                     };
-                    line.push_str("\n\rtaskctl > ");
+                    if streamed {
+                        // below the progress, the prompt comes back on its own
+                        print!("{}\n\r", line);
+                        stdout().flush().unwrap();
+                        line.clear();
+                    } else {
+                        line.push_str("\n\rtaskctl > ");
+                    }
                 }
                 Key::Char(c) => line.push(c),
                 _ => {}
@@ -1,4 +1,5 @@
-use bincode::{deserialize, serialize};
+use bincode::{deserialize_from, serialize};
+use chrono::{Duration, Local, NaiveTime, TimeZone};
 use serde::{Deserialize, Serialize};
 use std::io::prelude::*;
 use std::net::TcpStream;
@@ -19,6 +20,12 @@ pub enum State {
     //The process exited from the RUNNING state (expectedly or unexpectedly).
     FATAL,
     //The process could not be started successfully.
+    WAITING,
+    //The process is waiting for its start_conditions to be met.
+    QUEUED,
+    //The process is waiting for its start_stagger delay or for a free start slot (max_concurrent_starts).
+    SUCCEEDED,
+    //The oneshot process ran to completion with one of its exitcodes.
     #[default]
     UNKNOWN,
 }
@@ -42,7 +49,25 @@ pub enum CallOn {
     Stop(String),
     Restart(String),
     Status,
+    Details(String),
     Reload,
+    // program, apply its reload_signal or reload_command
+    ReloadApp(String),
+    // job, wait for its result
+    Run(String, bool),
+    // program, instances restarted at a time
+    RollingRestart(String, usize),
+    // process, replaced by a new generation started next to it
+    Replace(String),
+    // program, numprocs, write it back to the config
+    Scale(String, usize, bool),
+    // action, when to run it (unix seconds), its command line
+    Deferred(Box<CallOn>, i64, String),
+    Pending,
+    // id of a deferred action
+    Cancel(u64),
+    // from the server only: a line reported by a long command, its response follows
+    Progress,
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
@@ -56,6 +81,40 @@ impl Request {
         let args: Vec<String> = cmd.split(" ").map(|s| s.to_string()).collect();
         let len = args.len();
 
+        if let Some(flag) = args.iter().position(|arg| arg == "--at" || arg == "--in") {
+            return Self::parse_deferred(&args, flag, lst_proc).map(|cmd| Self { cmd, len });
+        }
+        if args[0] == "cancel" || args[0] == "Cancel" {
+            return match args.get(1).map(|id| id.trim_start_matches('#').parse::<u64>()) {
+                Some(Ok(id)) if len == 2 => Ok(Self { cmd: CallOn::Cancel(id), len }),
+                _ => Err("usage: cancel <id>".to_string()),
+            };
+        }
+        if args[0] == "Run" || args[0] == "run" {
+            return Self::parse_run(&args[1..]).map(|cmd| Self { cmd, len });
+        }
+        if args[0] == "reload-app" {
+            return match &args[1..] {
+                [program] => Ok(Self { cmd: CallOn::ReloadApp(program.clone()), len }),
+                _ => Err("usage: reload-app <program>".to_string()),
+            };
+        }
+        if args[0] == "Scale" || args[0] == "scale" {
+            return Self::parse_scale(&args[1..]).map(|cmd| Self { cmd, len });
+        }
+        if (args[0] == "Restart" || args[0] == "restart")
+            && args.get(1) == Some(&"--rolling".to_string())
+        {
+            return Self::parse_rolling(&args[2..]).map(|cmd| Self { cmd, len });
+        }
+        if (args[0] == "Restart" || args[0] == "restart")
+            && args.get(1) == Some(&"--replace".to_string())
+        {
+            return match &args[2..] {
+                [proc_name] => Ok(Self { cmd: CallOn::Replace(proc_name.clone()), len }),
+                _ => Err("usage: restart --replace <proc>".to_string()),
+            };
+        }
         if len <= 2 {
             return match Self::is_command(args, len, lst_proc) {
                 Ok(cmd) => Ok(Self { cmd, len }),
@@ -65,6 +124,95 @@ impl Request {
         Err("Too many arguments.".to_string())
     }
 
+    // `run <job> [--wait]`, the job is checked by the server
+    fn parse_run(args: &[String]) -> Result<CallOn, String> {
+        match args {
+            [job] if job != "--wait" => Ok(CallOn::Run(job.clone(), false)),
+            [job, wait] | [wait, job] if wait == "--wait" && job != "--wait" => {
+                Ok(CallOn::Run(job.clone(), true))
+            }
+            _ => Err("usage: run <job> [--wait]".to_string()),
+        }
+    }
+
+    // `restart --rolling <program> [batch]`, the program is checked by the server
+    fn parse_rolling(args: &[String]) -> Result<CallOn, String> {
+        match args {
+            [program] => Ok(CallOn::RollingRestart(program.clone(), 1)),
+            [program, batch] => match batch.parse::<usize>() {
+                Ok(batch) if batch > 0 => Ok(CallOn::RollingRestart(program.clone(), batch)),
+                _ => Err(format!("{}: batch must be a positive number", batch)),
+            },
+            _ => Err("usage: restart --rolling <program> [batch]".to_string()),
+        }
+    }
+
+    // `scale <program> <numprocs> [--save]`, the program is checked by the server
+    fn parse_scale(args: &[String]) -> Result<CallOn, String> {
+        let (program, numprocs, save) = match args {
+            [program, numprocs] => (program, numprocs, false),
+            [program, numprocs, save] if save == "--save" => (program, numprocs, true),
+            _ => return Err("usage: scale <program> <numprocs> [--save]".to_string()),
+        };
+        match numprocs.parse::<usize>() {
+            Ok(numprocs) => Ok(CallOn::Scale(program.clone(), numprocs, save)),
+            Err(_) => Err(format!("{}: numprocs must be a number", numprocs)),
+        }
+    }
+
+    // `<command> --at HH:MM` or `<command> --in <n>[s|m|h|d]`
+    fn parse_deferred(args: &[String], flag: usize, lst_proc: Vec<String>) -> Result<CallOn, String> {
+        let at = match &args[flag..] {
+            [flag, value] if flag == "--at" => Self::parse_at(value)?,
+            [_, value] => Self::parse_in(value)?,
+            _ => return Err("usage: <command> --at HH:MM | --in <n>[s|m|h|d]".to_string()),
+        };
+        let line = args[..flag].join(" ");
+        match Self::parse(&line, lst_proc)?.cmd {
+            CallOn::Status
+            | CallOn::Details(_)
+            | CallOn::Pending
+            | CallOn::Cancel(_)
+            | CallOn::Deferred(..)
+            | CallOn::Run(_, true) => Err(format!("{}: can't be deferred", line)),
+            cmd => Ok(CallOn::Deferred(Box::new(cmd), at, line)),
+        }
+    }
+
+    // next time the clock reads HH:MM[:SS]
+    fn parse_at(value: &str) -> Result<i64, String> {
+        let time = NaiveTime::parse_from_str(value, "%H:%M")
+            .or_else(|_| NaiveTime::parse_from_str(value, "%H:%M:%S"))
+            .map_err(|_| format!("{}: expected HH:MM", value))?;
+        let now = Local::now();
+        let mut day = now.date_naive();
+
+        loop {
+            // skips a time lost to a DST change
+            if let Some(at) = Local.from_local_datetime(&day.and_time(time)).earliest() {
+                if at > now {
+                    return Ok(at.timestamp());
+                }
+            }
+            day = day + Duration::days(1);
+        }
+    }
+
+    fn parse_in(value: &str) -> Result<i64, String> {
+        let digits = value.trim_end_matches(|c: char| c.is_ascii_alphabetic());
+        let unit = match &value[digits.len()..] {
+            "" | "s" => 1,
+            "m" => 60,
+            "h" => 3600,
+            "d" => 86400,
+            _ => return Err(format!("{}: expected <n>[s|m|h|d]", value)),
+        };
+        match digits.parse::<u32>() {
+            Ok(n) => Ok(Local::now().timestamp() + n as i64 * unit),
+            Err(_) => Err(format!("{}: expected <n>[s|m|h|d]", value)),
+        }
+    }
+
     fn proc_exist(proc_requested: String, lst_proc: Vec<String>) -> Result<String, String> {
         match lst_proc.iter().any(|x| x == &proc_requested) {
             true => return Ok(proc_requested),
@@ -81,6 +229,7 @@ impl Request {
             match &command[0][..] {
                 "Status" | "status" => Ok(CallOn::Status),
                 "Reload" | "reload" => Ok(CallOn::Reload),
+                "Pending" | "pending" => Ok(CallOn::Pending),
                 _ => Err(format!("*** Unknown syntax: {}", &command[0])),
             }
         } else {
@@ -89,6 +238,7 @@ impl Request {
                     "Start" | "start" => Ok(CallOn::Start(proc)),
                     "Stop" | "stop" => Ok(CallOn::Stop(proc)),
                     "Restart" | "restart" => Ok(CallOn::Restart(proc)),
+                    "Status" | "status" => Ok(CallOn::Details(proc)),
                     _ => Err(format!("** Unknown syntax: {:?}", command)),
                 },
                 Err(e) => Err(e),
@@ -97,12 +247,21 @@ impl Request {
     }
 
     pub fn send(command_prompt: &String, proc_list: Vec<String>) -> Result<Response, String> {
+        Self::send_with_progress(command_prompt, proc_list, |_| ())
+    }
+
+    /// As send, with the progress lines the command reports before its response.
+    pub fn send_with_progress(
+        command_prompt: &String,
+        proc_list: Vec<String>,
+        on_progress: impl FnMut(&str),
+    ) -> Result<Response, String> {
         match Self::parse(&command_prompt, proc_list) {
             Ok(cmd) => match TcpStream::connect("127.0.0.1:9003") {
                 Ok(mut stream) => {
                     let cmd: Vec<u8> = serialize(&cmd).unwrap();
                     stream.write(cmd.as_slice()).unwrap();
-                    return Self::response(stream);
+                    return Self::response(stream, on_progress);
                 }
                 Err(_e) => return Err(format!("http://localhost:9003 refused connection")),
             },
@@ -110,18 +269,85 @@ impl Request {
         }
     }
 
-    pub fn response(mut stream: TcpStream) -> Result<Response, String> {
-        let mut data = [0 as u8; 1500];
+    pub fn response(
+        mut stream: TcpStream,
+        mut on_progress: impl FnMut(&str),
+    ) -> Result<Response, String> {
+        // progress lines first, then the response, and the server closes the connection
+        loop {
+            let res: Response = deserialize_from(&mut stream).map_err(|e| format!("{e}"))?;
+            match (&res.cmd, &res.success) {
+                (CallOn::Progress, Ok(line)) => on_progress(line),
+                _ => return Ok(res),
+            }
+        }
+    }
+}
 
-        match stream.read(&mut data) {
-            Ok(size) => {
-                if size <= 1500 {
-                    let res: Response = deserialize(&data).unwrap();
-                    return Ok(res);
-                }
-                Err("Response exceed buffer size".to_string())
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_in_units() {
+        let now = Local::now().timestamp();
+        let within = |value: &str, secs: i64| {
+            let at = Request::parse_in(value).unwrap();
+            (now + secs..=now + secs + 1).contains(&at)
+        };
+
+        assert!(within("30", 30));
+        assert!(within("30s", 30));
+        assert!(within("10m", 600));
+        assert!(within("2h", 7200));
+        assert!(within("1d", 86400));
+    }
+
+    #[test]
+    fn parse_in_rejects() {
+        for value in ["", "m", "10x", "-5m", "1.5h", "10mm", "99999999999s"] {
+            assert!(Request::parse_in(value).is_err(), "{value}");
+        }
+    }
+
+    #[test]
+    fn parse_at_next_occurrence() {
+        let now = Local::now();
+
+        for value in ["00:00", "12:30", "23:59:59"] {
+            let at = Request::parse_at(value).unwrap();
+            let expected = NaiveTime::parse_from_str(value, "%H:%M:%S")
+                .or_else(|_| NaiveTime::parse_from_str(value, "%H:%M"))
+                .unwrap();
+            assert!(at > now.timestamp(), "{value}");
+            // within a day, plus a DST change
+            assert!(at <= now.timestamp() + 25 * 3600, "{value}");
+            assert_eq!(Local.timestamp_opt(at, 0).unwrap().time(), expected, "{value}");
+        }
+    }
+
+    #[test]
+    fn parse_at_rejects() {
+        for value in ["", "24:00", "12:60", "12", "noon", "12:30pm"] {
+            assert!(Request::parse_at(value).is_err(), "{value}");
+        }
+    }
+
+    #[test]
+    fn parse_deferred_commands() {
+        let procs = vec!["web_0".to_string()];
+        let parse = |line: &str| Request::parse(&line.to_string(), procs.clone()).map(|r| r.cmd);
+
+        match parse("stop web_0 --in 10m") {
+            Ok(CallOn::Deferred(cmd, _, line)) => {
+                assert_eq!(*cmd, CallOn::Stop("web_0".to_string()));
+                assert_eq!(line, "stop web_0");
             }
-            Err(e) => Err(format!("{e}")),
+            other => panic!("{:?}", other),
         }
+        assert!(parse("status --in 10m").is_err());
+        assert!(parse("pending --at 12:00").is_err());
+        assert!(parse("stop web_0 --in").is_err());
+        assert!(parse("stop nope_0 --in 10m").is_err());
     }
 }
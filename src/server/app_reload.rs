@@ -0,0 +1,259 @@
+use chrono::Local;
+use nix::sys::signal::{kill, Signal};
+use nix::unistd::Pid;
+use std::process::{Command, Stdio};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use super::conf::Taskmaster;
+use super::parse::File;
+use super::task::Task;
+use share_structures::{CallOn, Response, State};
+
+/// How a program reloads its own config: `reload_signal` or `reload_command`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum AppReload {
+    Signal(Signal),
+    Command(String),
+}
+
+impl AppReload {
+    pub fn from_file(parse_file: &File) -> Option<Self> {
+        match (&parse_file.reload_signal, &parse_file.reload_command) {
+            (Some(signal), _) => Some(AppReload::Signal(*signal)),
+            (None, Some(cmd)) => Some(AppReload::Command(cmd.clone())),
+            (None, None) => None,
+        }
+    }
+}
+
+// a reload_command still running after this failed
+const COMMAND_TIMEOUT: Duration = Duration::from_secs(30);
+const COMMAND_POLL: Duration = Duration::from_millis(50);
+
+// the instances of a program, with their pid if RUNNING
+type ReloadTargets = Vec<(String, Option<u32>)>;
+
+impl AppReload {
+    // apply it to one instance, $MAINPID is its pid for a reload_command
+    fn apply(&self, name: &str, pid: Option<u32>) -> Result<String, String> {
+        let pid = match pid {
+            Some(pid) => pid,
+            None => return Err(format!("{}: ERROR (not running)", name)),
+        };
+
+        match self {
+            AppReload::Signal(signal) => match kill(Pid::from_raw(pid as i32), *signal) {
+                Ok(()) => Ok(format!("{}: sent {:?} to pid {}", name, signal, pid)),
+                Err(e) => Err(format!("{}: ERROR (can't send {:?}: {})", name, signal, e)),
+            },
+            AppReload::Command(cmd) => {
+                let child = Command::new("/bin/sh")
+                    .args(["-c", cmd])
+                    .env("MAINPID", pid.to_string())
+                    .stdin(Stdio::null())
+                    .stdout(Stdio::null())
+                    .stderr(Stdio::null())
+                    .spawn();
+                let mut child = match child {
+                    Ok(child) => child,
+                    Err(e) => return Err(format!("{}: ERROR (can't run `{}`: {})", name, cmd, e)),
+                };
+                let deadline = Instant::now() + COMMAND_TIMEOUT;
+                loop {
+                    match child.try_wait() {
+                        Ok(Some(status)) if status.success() => {
+                            return Ok(format!("{}: `{}` succeeded", name, cmd))
+                        }
+                        Ok(Some(status)) => {
+                            return Err(format!("{}: ERROR (`{}` {})", name, cmd, status))
+                        }
+                        Ok(None) if Instant::now() >= deadline => {
+                            let _ = child.kill();
+                            let _ = child.wait();
+                            return Err(format!(
+                                "{}: ERROR (`{}` timed out after {}s)",
+                                name,
+                                cmd,
+                                COMMAND_TIMEOUT.as_secs()
+                            ));
+                        }
+                        Ok(None) => thread::sleep(COMMAND_POLL),
+                        Err(e) => {
+                            return Err(format!("{}: ERROR (can't run `{}`: {})", name, cmd, e))
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    // apply it to every instance, Err if any failed
+    fn apply_all(&self, targets: &[(String, Option<u32>)]) -> Result<String, String> {
+        let results: Vec<Result<String, String>> = targets
+            .iter()
+            .map(|(name, pid)| self.apply(name, *pid))
+            .collect();
+        let lines: Vec<String> = results
+            .iter()
+            .map(|res| match res {
+                Ok(line) | Err(line) => line.clone(),
+            })
+            .collect();
+        for line in &lines {
+            println!(
+                "{} INFO reload-app: {}",
+                Local::now().format("%d/%m/%Y %H:%M:%S"),
+                line
+            );
+        }
+        match results.iter().all(|res| res.is_ok()) {
+            true => Ok(lines.join("\n\r")),
+            false => Err(lines.join("\n\r")),
+        }
+    }
+}
+
+impl Task {
+    // instances alive right now, by name and pid
+    fn running_pids(&self) -> Vec<(String, u32)> {
+        self.process_lst
+            .iter()
+            .filter(|proc| proc.state == State::RUNNING && !proc.retiring)
+            .filter_map(|proc| proc.pid.map(|pid| (proc.get_name(), pid)))
+            .collect()
+    }
+}
+
+impl Taskmaster {
+    // the reload of the program and its instances, with their pid if RUNNING
+    fn reload_targets(&self, name: &str) -> Result<(AppReload, ReloadTargets), String> {
+        let task = match self.task_lst.iter().find(|task| task.getnameparse() == name) {
+            Some(task) => task,
+            None => return Err(format!("{}: ERROR (no such program)", name)),
+        };
+        let reload = match AppReload::from_file(&task.parse_file) {
+            Some(reload) => reload,
+            None => {
+                return Err(format!(
+                    "{}: ERROR (no reload_signal or reload_command)",
+                    name
+                ))
+            }
+        };
+        let targets = task
+            .process_lst
+            .iter()
+            .filter(|proc| !proc.retiring)
+            .map(|proc| match proc.state {
+                State::RUNNING => (proc.get_name(), proc.pid),
+                _ => (proc.get_name(), None),
+            })
+            .collect();
+        Ok((reload, targets))
+    }
+
+    /// `reload-app` from a deferred action: a reload_command runs from a
+    /// thread of its own, the result is only logged.
+    pub fn reload_app(&mut self, name: String) -> Response {
+        let res = match self.reload_targets(&name) {
+            Ok((AppReload::Command(cmd), targets)) => {
+                let res = Ok(format!(
+                    "{}: running `{}` on {} instances",
+                    name,
+                    cmd,
+                    targets.len()
+                ));
+                thread::spawn(move || AppReload::Command(cmd).apply_all(&targets));
+                res
+            }
+            Ok((reload, targets)) => reload.apply_all(&targets),
+            Err(e) => Err(e),
+        };
+
+        Response {
+            cmd: CallOn::ReloadApp(name),
+            content: self.get_proc_list(),
+            success: res,
+        }
+    }
+}
+
+/// `reload-app <program>`: a reload_signal is sent under the lock, a
+/// reload_command runs once it is released.
+pub fn reload_app(taskmaster: &Arc<Mutex<Taskmaster>>, name: String) -> Response {
+    let mut app = taskmaster.lock().unwrap();
+    let targets = app.reload_targets(&name);
+    let res = match targets {
+        Ok((reload @ AppReload::Command(_), targets)) => {
+            drop(app);
+            let res = reload.apply_all(&targets);
+            app = taskmaster.lock().unwrap();
+            res
+        }
+        Ok((reload, targets)) => reload.apply_all(&targets),
+        Err(e) => Err(e),
+    };
+
+    Response {
+        cmd: CallOn::ReloadApp(name),
+        content: app.get_proc_list(),
+        success: res,
+    }
+}
+
+/// With `reload_verify`, wait that long after the reload and check that every
+/// instance signaled is still up with the same pid.
+pub fn verify_reload(
+    taskmaster: &Arc<Mutex<Taskmaster>>,
+    name: String,
+    reloaded: Response,
+) -> Response {
+    let (delay, before) = {
+        let app = taskmaster.lock().unwrap();
+        match app
+            .task_lst
+            .iter()
+            .find(|task| task.getnameparse() == &name)
+        {
+            Some(task) => match task.parse_file.reload_verify {
+                Some(secs) if secs > 0 => (secs as u64, task.running_pids()),
+                _ => return reloaded,
+            },
+            None => return reloaded,
+        }
+    };
+    thread::sleep(Duration::from_secs(delay));
+
+    let mut app = taskmaster.lock().unwrap();
+    let after = app
+        .task_lst
+        .iter()
+        .find(|task| task.getnameparse() == &name)
+        .map(|task| task.running_pids())
+        .unwrap_or_default();
+    let mut lines = vec![reloaded.success.clone().unwrap_or_default()];
+    let mut ok = true;
+    for (proc_name, pid) in before {
+        match after.contains(&(proc_name.clone(), pid)) {
+            true => lines.push(format!("{}: still running after {}s", proc_name, delay)),
+            false => {
+                ok = false;
+                lines.push(format!(
+                    "{}: ERROR (pid {} gone {}s after the reload)",
+                    proc_name, pid, delay
+                ));
+            }
+        }
+    }
+
+    Response {
+        cmd: CallOn::ReloadApp(name),
+        content: app.get_proc_list(),
+        success: match ok {
+            true => Ok(lines.join("\n\r")),
+            false => Err(lines.join("\n\r")),
+        },
+    }
+}
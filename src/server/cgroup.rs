@@ -0,0 +1,265 @@
+use chrono::Local;
+use nix::libc;
+use nix::sys::signal::{kill, Signal};
+use nix::unistd::Pid;
+use std::ffi::CString;
+use std::fs;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::Duration;
+use yaml_rust::Yaml;
+
+use super::parse::File;
+use super::proc::Proc;
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct CgroupLimits {
+    pub memory_max: Option<String>,
+    pub cpu_max: Option<String>,
+    pub pids_max: Option<String>,
+    pub io_weight: Option<String>,
+}
+
+fn yaml_to_string(yaml: &Yaml) -> Option<String> {
+    match yaml {
+        Yaml::Integer(value) => Some(value.to_string()),
+        Yaml::String(value) => Some(value.clone()),
+        _ => None,
+    }
+}
+
+impl CgroupLimits {
+    pub fn from_yaml(yaml_file: &Yaml) -> Self {
+        CgroupLimits {
+            memory_max: yaml_to_string(&yaml_file["memory_max"]),
+            cpu_max: yaml_to_string(&yaml_file["cpu_max"]).map(|cpu| cpu_max_from_string(&cpu)),
+            pids_max: yaml_to_string(&yaml_file["pids_max"]),
+            io_weight: yaml_to_string(&yaml_file["io_weight"]).map(|w| format!("default {}", w)),
+        }
+    }
+
+    // (controller, file, value) for every configured limit
+    fn files(&self) -> Vec<(&'static str, &'static str, &String)> {
+        let limits = [
+            ("memory", "memory.max", &self.memory_max),
+            ("cpu", "cpu.max", &self.cpu_max),
+            ("pids", "pids.max", &self.pids_max),
+            ("io", "io.weight", &self.io_weight),
+        ];
+
+        limits
+            .into_iter()
+            .filter_map(|(controller, file, value)| value.as_ref().map(|v| (controller, file, v)))
+            .collect()
+    }
+}
+
+// `50%` is a shortcut for half a CPU, anything else is written as is ("50000 100000")
+fn cpu_max_from_string(cpu: &str) -> String {
+    match cpu
+        .strip_suffix('%')
+        .map(|percent| percent.trim().parse::<u64>())
+    {
+        Some(Ok(percent)) => format!("{} 100000", percent * 1000),
+        _ => cpu.to_string(),
+    }
+}
+
+fn write_file(path: &Path, value: &str) -> Result<(), String> {
+    fs::write(path, value)
+        .map_err(|e| format!("can't write {:?} to {}: {e}", value, path.display()))
+}
+
+fn enable_controllers(dir: &Path, controllers: &[&str]) -> Result<(), String> {
+    for controller in controllers {
+        write_file(
+            &dir.join("cgroup.subtree_control"),
+            &format!("+{}", controller),
+        )?;
+    }
+    Ok(())
+}
+
+// how long remove waits for the killed processes to go
+const REMOVE_TRIES: u32 = 20;
+const REMOVE_POLL: Duration = Duration::from_millis(5);
+
+#[derive(Debug)]
+pub struct Cgroup {
+    pub path: PathBuf,
+    pub limits: CgroupLimits,
+    oom_kills: u64,
+}
+
+impl Cgroup {
+    /// `<parent>/<program>/<process>`, limits apply to each process.
+    pub fn new(parent: &str, program: &str, proc_name: &str, limits: CgroupLimits) -> Self {
+        Cgroup {
+            path: Path::new(parent).join(program).join(proc_name),
+            limits,
+            oom_kills: 0,
+        }
+    }
+
+    /// Create the cgroup (and its ancestors) and write the limits, before every spawn.
+    pub fn prepare(&mut self) -> Result<(), String> {
+        let program_dir = self.path.parent().expect("cgroup has a parent");
+        let parent_dir = program_dir.parent().expect("cgroup has a parent");
+        let limit_files = self.limits.files();
+        let controllers: Vec<&str> = limit_files.iter().map(|(c, _, _)| *c).collect();
+
+        fs::create_dir_all(&self.path)
+            .map_err(|e| format!("can't create cgroup {}: {e}", self.path.display()))?;
+        enable_controllers(parent_dir, &controllers)?;
+        enable_controllers(program_dir, &controllers)?;
+        for (_, file, value) in limit_files {
+            write_file(&self.path.join(file), value)?;
+        }
+        // leftovers of the previous run
+        self.kill_all();
+        self.oom_kills = self.read_oom_kills();
+        Ok(())
+    }
+
+    pub fn pids(&self) -> Vec<i32> {
+        fs::read_to_string(self.path.join("cgroup.procs"))
+            .unwrap_or_default()
+            .lines()
+            .filter_map(|pid| pid.trim().parse().ok())
+            .collect()
+    }
+
+    pub fn kill_all(&self) {
+        if fs::write(self.path.join("cgroup.kill"), "1").is_err() {
+            // cgroup.kill needs linux 5.14
+            for pid in self.pids() {
+                let _ = kill(Pid::from_raw(pid), Signal::SIGKILL);
+            }
+        }
+    }
+
+    pub fn read_oom_kills(&self) -> u64 {
+        fs::read_to_string(self.path.join("memory.events"))
+            .unwrap_or_default()
+            .lines()
+            .find_map(|line| line.strip_prefix("oom_kill "))
+            .and_then(|count| count.trim().parse().ok())
+            .unwrap_or(0)
+    }
+
+    /// Remove the cgroup once the processes killed in it are gone, and the
+    /// one of the program with its last process.
+    pub fn remove(&self) {
+        for _ in 0..REMOVE_TRIES {
+            match fs::remove_dir(&self.path) {
+                Err(e) if e.raw_os_error() == Some(libc::EBUSY) => thread::sleep(REMOVE_POLL),
+                _ => break,
+            }
+        }
+        if let Some(program_dir) = self.path.parent() {
+            let _ = fs::remove_dir(program_dir);
+        }
+    }
+
+    /// True when the OOM killer fired in this cgroup since the last spawn.
+    pub fn oom_killed(&mut self) -> bool {
+        let count = self.read_oom_kills();
+        let killed = count > self.oom_kills;
+
+        self.oom_kills = count;
+        killed
+    }
+}
+
+// rmdir fails while the cgroup isn't empty, never kill from here: the
+// configs loaded by a reload have cgroups of running processes too
+impl Drop for Cgroup {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir(&self.path);
+        if let Some(program_dir) = self.path.parent() {
+            let _ = fs::remove_dir(program_dir);
+        }
+    }
+}
+
+impl Proc {
+    pub fn setup_command_cgroup(&mut self, parse_file: &File, program: &str) {
+        let parent = match &parse_file.cgroup_parent {
+            Some(parent) => parent,
+            None => return,
+        };
+        let cgroup = Cgroup::new(
+            parent,
+            program,
+            &self.get_name(),
+            parse_file.cgroup_limits.clone(),
+        );
+        let procs = CString::new(cgroup.path.join("cgroup.procs").as_os_str().as_bytes())
+            .expect("cgroup path with a nul byte");
+        let label = format!("join cgroup {}", cgroup.path.display());
+        let step = self.spawn_step.clone();
+
+        if let Some(command) = &mut self.command {
+            unsafe {
+                command.pre_exec(move || {
+                    step.set(&label);
+                    let fd = libc::open(procs.as_ptr(), libc::O_WRONLY | libc::O_CLOEXEC);
+                    if fd < 0 {
+                        return Err(std::io::Error::last_os_error());
+                    }
+                    // "0" moves the writing process
+                    let res = libc::write(fd, b"0".as_ptr() as *const libc::c_void, 1);
+                    libc::close(fd);
+                    match res {
+                        1 => Ok(()),
+                        _ => Err(std::io::Error::last_os_error()),
+                    }
+                });
+            }
+        }
+        self.cgroup = Some(cgroup);
+    }
+
+    pub fn prepare_cgroup(&mut self) -> Result<(), String> {
+        match &mut self.cgroup {
+            Some(cgroup) => cgroup.prepare(),
+            None => Ok(()),
+        }
+    }
+
+    /// After the main process exited: kill what it left behind and report OOM kills.
+    pub fn reap_cgroup(&mut self) -> bool {
+        let oom_killed = match &mut self.cgroup {
+            Some(cgroup) => {
+                cgroup.kill_all();
+                let oom_killed = cgroup.oom_killed();
+                // made again by the next spawn
+                cgroup.remove();
+                oom_killed
+            }
+            None => false,
+        };
+
+        if oom_killed {
+            println!(
+                "{} WARN oom: {} was killed by the OOM killer (memory_max {})",
+                Local::now().format("%d/%m/%Y %H:%M:%S"),
+                self.get_name(),
+                self.cgroup
+                    .as_ref()
+                    .and_then(|cgroup| cgroup.limits.memory_max.clone())
+                    .unwrap_or("max".to_string())
+            );
+            self.description = format!("OOM killed, {}", self.description);
+        }
+        oom_killed
+    }
+
+    pub fn kill_cgroup(&self) {
+        if let Some(cgroup) = &self.cgroup {
+            cgroup.kill_all();
+        }
+    }
+}
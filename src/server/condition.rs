@@ -0,0 +1,179 @@
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::sync::mpsc::{self, TryRecvError};
+use std::thread;
+use std::time::{Duration, Instant};
+use yaml_rust::Yaml;
+
+use super::proc::Proc;
+use share_structures::State;
+
+// a `command` condition still running after this is unmet
+const COMMAND_TIMEOUT: Duration = Duration::from_secs(5);
+const COMMAND_POLL: Duration = Duration::from_millis(50);
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum StartCondition {
+    PathExists(String),
+    PortFree(u16),
+    PortListening(u16),
+    Command(String),
+}
+
+impl StartCondition {
+    /// Err with the reason the condition is ignored.
+    pub fn from_yaml(yaml: &Yaml) -> Result<Self, String> {
+        if let Some(path) = yaml["path"].as_str() {
+            Ok(StartCondition::PathExists(path.to_string()))
+        } else if let Some(port) = yaml["port_free"].as_i64() {
+            port_in_range(port).map(StartCondition::PortFree)
+        } else if let Some(port) = yaml["port_listening"].as_i64() {
+            port_in_range(port).map(StartCondition::PortListening)
+        } else {
+            yaml["command"]
+                .as_str()
+                .map(|cmd| StartCondition::Command(cmd.to_string()))
+                .ok_or(format!("unknown condition {:?}", yaml))
+        }
+    }
+
+    /// Ok when the condition holds, otherwise the reason shown while WAITING.
+    pub fn check(&self) -> Result<(), String> {
+        match self {
+            StartCondition::PathExists(path) => match Path::new(path).exists() {
+                true => Ok(()),
+                false => Err(format!("path {} does not exist", path)),
+            },
+            StartCondition::PortFree(port) => match TcpListener::bind(("127.0.0.1", *port)) {
+                Ok(_) => Ok(()),
+                Err(_) => Err(format!("port {} is in use", port)),
+            },
+            StartCondition::PortListening(port) => {
+                let addr = SocketAddr::from(([127, 0, 0, 1], *port));
+                match TcpStream::connect_timeout(&addr, Duration::from_millis(500)) {
+                    Ok(_) => Ok(()),
+                    Err(_) => Err(format!("nothing listening on port {}", port)),
+                }
+            }
+            StartCondition::Command(cmd) => {
+                let child = Command::new("/bin/sh")
+                    .args(["-c", cmd])
+                    .stdin(Stdio::null())
+                    .stdout(Stdio::null())
+                    .stderr(Stdio::null())
+                    .spawn();
+                let mut child = match child {
+                    Ok(child) => child,
+                    Err(e) => return Err(format!("`{}` failed: {e}", cmd)),
+                };
+                let deadline = Instant::now() + COMMAND_TIMEOUT;
+                loop {
+                    match child.try_wait() {
+                        Ok(Some(status)) if status.success() => return Ok(()),
+                        Ok(Some(status)) => return Err(format!("`{}` {}", cmd, status)),
+                        Ok(None) if Instant::now() >= deadline => {
+                            let _ = child.kill();
+                            let _ = child.wait();
+                            return Err(format!(
+                                "`{}` timed out after {}s",
+                                cmd,
+                                COMMAND_TIMEOUT.as_secs()
+                            ));
+                        }
+                        Ok(None) => thread::sleep(COMMAND_POLL),
+                        Err(e) => return Err(format!("`{}` failed: {e}", cmd)),
+                    }
+                }
+            }
+        }
+    }
+}
+
+pub fn parse_start_conditions(yaml: &Yaml) -> Vec<StartCondition> {
+    yaml.clone()
+        .into_iter()
+        .filter_map(|value| {
+            StartCondition::from_yaml(&value)
+                .map_err(|e| println!("start_conditions: ignoring {}", e))
+                .ok()
+        })
+        .collect()
+}
+
+// 0 would bind any free port, and a cast would wrap larger ones
+fn port_in_range(port: i64) -> Result<u16, String> {
+    match u16::try_from(port) {
+        Ok(port) if port != 0 => Ok(port),
+        _ => Err(format!("port {} (expected 1 to 65535)", port)),
+    }
+}
+
+impl Proc {
+    /// None once the conditions were found met. They are checked by a thread
+    /// of their own so the supervisor never waits on them: until it reports,
+    /// the last reason seen stands.
+    pub fn unmet_condition(&mut self) -> Option<String> {
+        if self.start_conditions.is_empty() {
+            return None;
+        }
+        let reason = match self.condition_check.as_ref().map(|check| check.try_recv()) {
+            Some(Ok(Ok(()))) => {
+                self.condition_check = None;
+                return None;
+            }
+            Some(Ok(Err(reason))) => reason,
+            Some(Err(TryRecvError::Empty)) => {
+                return Some(match self.state {
+                    State::WAITING => self.description.clone(),
+                    _ => "checking start conditions".to_string(),
+                })
+            }
+            // first check, or the last one never reported
+            Some(Err(TryRecvError::Disconnected)) | None => {
+                "checking start conditions".to_string()
+            }
+        };
+
+        let (tx, rx) = mpsc::channel();
+        let conditions = self.start_conditions.clone();
+        thread::spawn(move || {
+            let res = match conditions.iter().find_map(|condition| condition.check().err()) {
+                Some(reason) => Err(reason),
+                None => Ok(()),
+            };
+            let _ = tx.send(res);
+        });
+        self.condition_check = Some(rx);
+        Some(reason)
+    }
+
+    pub fn change_to_waiting(&mut self, reason: String) {
+        self.description = reason;
+        if self.state != State::WAITING {
+            self.change_state(State::WAITING);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use yaml_rust::YamlLoader;
+
+    fn condition(yaml: &str) -> Result<StartCondition, String> {
+        StartCondition::from_yaml(&YamlLoader::load_from_str(yaml).unwrap()[0])
+    }
+
+    #[test]
+    fn port_out_of_range_is_refused() {
+        assert_eq!(condition("port_free: 8080"), Ok(StartCondition::PortFree(8080)));
+        assert_eq!(
+            condition("port_listening: 65535"),
+            Ok(StartCondition::PortListening(65535))
+        );
+        assert!(condition("port_free: 0").is_err());
+        assert!(condition("port_free: -1").is_err());
+        assert!(condition("port_listening: 65536").is_err());
+    }
+}
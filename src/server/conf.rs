@@ -2,9 +2,10 @@ use core::fmt;
 use nix::unistd::{getpid, Pid};
 use yaml_rust::Yaml;
 
+use super::deferred::{default_state_file, DeferredAction};
 use super::proc::Proc;
 use super::task::Task;
-use super::tool::file_to_yaml;
+use super::tool::{file_to_yaml, parse_to_string};
 use share_structures::{CallOn, Prog, Response};
 
 #[derive(Debug, Clone)]
@@ -31,10 +32,32 @@ pub struct Taskmaster {
     pub config: Config,
     pub task_lst: Vec<Task>,
     pub nprocs: i64,
+    pub max_concurrent_starts: Option<usize>,
+    pub critical_exitcode: i32,
+    pub shutdown_code: Option<i32>,
+    // numprocs changed by `scale` without --save
+    pub scaled: bool,
+    // actions run later, kept in state_file across restarts
+    pub deferred: Vec<DeferredAction>,
+    pub state_file: String,
+    // never reused, kept in state_file too
+    pub next_deferred_id: u64,
     //Bonus :file pid
     //Bonus: file log
 }
 
+// 0 would make a critical failure look like a clean exit
+fn critical_exitcode(config: &Yaml) -> i32 {
+    match config["critical_exitcode"].as_i64() {
+        Some(0) => {
+            println!("critical_exitcode: ignoring 0 (a clean exit), using 1");
+            1
+        }
+        Some(code) => code as i32,
+        None => 1,
+    }
+}
+
 impl fmt::Debug for Taskmaster {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         f.debug_struct("Taskmaster")
@@ -54,6 +77,16 @@ impl Taskmaster {
             config: conf.clone(),
             task_lst: Self::set_task_lst(&conf.yaml),
             nprocs: 0,
+            max_concurrent_starts: conf.yaml["max_concurrent_starts"]
+                .as_i64()
+                .map(|max| max.max(1) as usize),
+            critical_exitcode: critical_exitcode(&conf.yaml),
+            shutdown_code: None,
+            scaled: false,
+            deferred: vec![],
+            state_file: parse_to_string(conf.yaml["state_file"].as_str())
+                .unwrap_or(default_state_file(path)),
+            next_deferred_id: 1,
         }
     }
 
@@ -67,6 +100,9 @@ impl Taskmaster {
 
         vec_task.iter_mut().for_each(|task| {
             task.parse_file.init_args();
+            if task.parse_file.cgroup_parent.is_none() {
+                task.parse_file.cgroup_parent = parse_to_string(config["cgroup_parent"].as_str());
+            }
         });
         vec_task
     }
@@ -111,8 +147,13 @@ impl Taskmaster {
     }
 
     pub fn start(&mut self, name: String) -> Response {
-        let proc = self.get_proc_by_name(name.clone()).unwrap();
-        let res = proc.start();
+        let res = match self.running_conflict(&name) {
+            Some(other) => Err(format!(
+                "{}: ERROR (conflicts with {}, which is running)",
+                name, other
+            )),
+            None => self.get_proc_by_name(name.clone()).unwrap().start(),
+        };
 
         Response {
             cmd: CallOn::Start(name),
@@ -141,8 +182,13 @@ impl Taskmaster {
     }
 
     pub fn restart(&mut self, name: String) -> Response {
-        let proc = self.get_proc_by_name(name.clone()).unwrap();
-        let res = proc.restart();
+        let res = match self.running_conflict(&name) {
+            Some(other) => Err(format!(
+                "{}: ERROR (conflicts with {}, which is running)",
+                name, other
+            )),
+            None => self.get_proc_by_name(name.clone()).unwrap().restart(),
+        };
 
         Response {
             cmd: CallOn::Restart(name),
@@ -155,13 +201,16 @@ impl Taskmaster {
         let new_conf = file_to_yaml(&self.config.path);
         let res: Result<String, String>;
 
-        if new_conf == self.config.yaml {
+        if new_conf == self.config.yaml && !self.scaled {
             res = Ok(format!("Reload file with success: 0 change"));
         } else {
             let mut new = Taskmaster::new(&self.config.path);
 
             new.set_all_command();
             self.update_config();
+            self.scaled = false;
+            self.max_concurrent_starts = new.max_concurrent_starts;
+            self.critical_exitcode = new.critical_exitcode;
             self.remove_tasks(&mut new);
             self.add_tasks(new);
             self.start_all_task();
@@ -184,9 +233,10 @@ impl Taskmaster {
                     // set new config to task
                     curr.parse_doc_yaml = new_t.parse_doc_yaml.clone();
                     curr.parse_file = new_t.parse_file.clone();
+                    curr.reload_sockets();
                     // Control numprocs add if it needed
                     curr.add_nb_process(
-                        new_t.process_lst.len() as i64 - curr.process_lst.len() as i64,
+                        new_t.process_lst.len() as i64 - curr.active_count(),
                     );
                 }
                 Err(_) => self.task_lst.push(new_t),
@@ -204,13 +254,12 @@ impl Taskmaster {
                     // set new config to task
                     task.parse_doc_yaml = new_task.parse_doc_yaml.clone();
                     task.parse_file = new_task.parse_file.clone();
-                    task.process_lst.iter_mut().for_each(|proc| {
-                            proc.stopsignal = new_task.parse_file.stopsignal
-                        }
-                    );
+                    task.process_lst
+                        .iter_mut()
+                        .for_each(|proc| proc.stopsignal = new_task.parse_file.stopsignal);
                     // control numprocs, rm proc if needed
                     task.remove_nb_process(
-                        task.process_lst.len() as i64 - new_task.process_lst.len() as i64,
+                        task.active_count() - new_task.process_lst.len() as i64,
                     );
                 }
                 Err(_) => task.remove_all_process(),
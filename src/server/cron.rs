@@ -0,0 +1,456 @@
+use chrono::{
+    DateTime, Datelike, Duration, Local, NaiveDate, NaiveDateTime, TimeZone, Timelike, Utc,
+};
+use chrono_tz::Tz;
+use yaml_rust::Yaml;
+
+use super::parse::File;
+use super::proc::Proc;
+use share_structures::State;
+
+const MONTH_NAMES: [&str; 12] = [
+    "jan", "feb", "mar", "apr", "may", "jun", "jul", "aug", "sep", "oct", "nov", "dec",
+];
+const DAY_NAMES: [&str; 7] = ["sun", "mon", "tue", "wed", "thu", "fri", "sat"];
+
+/// What a tick does when the previous run is still there.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Overlap {
+    Skip,
+    // start again as soon as the previous run exits
+    Queue,
+    // stop the previous run and start a new one
+    Kill,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum Zone {
+    Local,
+    Tz(Tz),
+}
+
+impl Zone {
+    fn naive(&self, date: DateTime<Utc>) -> NaiveDateTime {
+        match self {
+            Zone::Local => date.with_timezone(&Local).naive_local(),
+            Zone::Tz(tz) => date.with_timezone(tz).naive_local(),
+        }
+    }
+
+    // None in a DST gap, the earliest of the two in a DST overlap
+    fn utc(&self, naive: &NaiveDateTime) -> Option<DateTime<Utc>> {
+        match self {
+            Zone::Local => Local
+                .from_local_datetime(naive)
+                .earliest()
+                .map(|date| date.with_timezone(&Utc)),
+            Zone::Tz(tz) => tz
+                .from_local_datetime(naive)
+                .earliest()
+                .map(|date| date.with_timezone(&Utc)),
+        }
+    }
+
+    pub fn format(&self, date: DateTime<Utc>) -> String {
+        match self {
+            Zone::Local => date
+                .with_timezone(&Local)
+                .format("%d/%m/%Y %H:%M")
+                .to_string(),
+            Zone::Tz(tz) => date
+                .with_timezone(tz)
+                .format("%d/%m/%Y %H:%M %Z")
+                .to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Schedule {
+    pub expr: String,
+    // one bit per allowed value of each field
+    minutes: u64,
+    hours: u64,
+    days: u64,
+    months: u64,
+    weekdays: u64,
+    // `*` day of month / day of week, cron ORs the two fields only when both are set
+    any_day: bool,
+    any_weekday: bool,
+    pub overlap: Overlap,
+    pub zone: Zone,
+    pub next_run: Option<DateTime<Utc>>,
+    pub last_run: Option<DateTime<Utc>>,
+    // a tick came while the previous run was still there (overlap: queue)
+    pub pending: bool,
+}
+
+fn parse_value(value: &str, names: &[&str], first: u32) -> Option<u32> {
+    match names
+        .iter()
+        .position(|name| value.eq_ignore_ascii_case(name))
+    {
+        Some(i) => Some(i as u32 + first),
+        None => value.parse().ok(),
+    }
+}
+
+// `*`, `5`, `1-5`, `*/15`, `10-30/5`, `mon-fri`, `1,15`
+fn parse_field(field: &str, min: u32, max: u32, names: &[&str]) -> Option<u64> {
+    let mut bits = 0;
+
+    for part in field.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => (range, step.parse::<u32>().ok().filter(|s| *s > 0)?),
+            None => (part, 1),
+        };
+        let (first, last) = match range {
+            "*" => (min, max),
+            _ => match range.split_once('-') {
+                Some((first, last)) => (
+                    parse_value(first, names, min)?,
+                    parse_value(last, names, min)?,
+                ),
+                // `5/10` is 5, 15, 25...
+                None => {
+                    let value = parse_value(range, names, min)?;
+                    (value, if part.contains('/') { max } else { value })
+                }
+            },
+        };
+        if first < min || last > max || first > last {
+            return None;
+        }
+        for value in (first..=last).step_by(step as usize) {
+            bits |= 1 << value;
+        }
+    }
+    Some(bits)
+}
+
+fn parse_overlap(yaml: &Yaml) -> Overlap {
+    match yaml.as_str() {
+        None | Some("skip") => Overlap::Skip,
+        Some("queue") => Overlap::Queue,
+        Some("kill") => Overlap::Kill,
+        Some(other) => {
+            println!("overlap: ignoring unknown policy {}, using skip", other);
+            Overlap::Skip
+        }
+    }
+}
+
+fn parse_zone(yaml: &Yaml) -> Zone {
+    match yaml.as_str() {
+        None | Some("local") => Zone::Local,
+        Some(name) => match name.parse::<Tz>() {
+            Ok(tz) => Zone::Tz(tz),
+            Err(_) => {
+                println!(
+                    "timezone: ignoring unknown timezone {}, using local time",
+                    name
+                );
+                Zone::Local
+            }
+        },
+    }
+}
+
+impl Schedule {
+    /// `schedule: "*/5 * * * *"` (or `@hourly`, `@daily`...), `overlap` and `timezone`.
+    pub fn from_yaml(yaml_file: &Yaml) -> Option<Self> {
+        let expr = yaml_file["schedule"].as_str()?;
+
+        match Schedule::parse(expr) {
+            Some(mut schedule) => {
+                schedule.overlap = parse_overlap(&yaml_file["overlap"]);
+                schedule.zone = parse_zone(&yaml_file["timezone"]);
+                Some(schedule)
+            }
+            None => {
+                println!("schedule: ignoring invalid schedule {:?}", expr);
+                None
+            }
+        }
+    }
+
+    pub fn parse(expr: &str) -> Option<Self> {
+        let fields: Vec<&str> = match expr.trim() {
+            "@yearly" | "@annually" => vec!["0", "0", "1", "1", "*"],
+            "@monthly" => vec!["0", "0", "1", "*", "*"],
+            "@weekly" => vec!["0", "0", "*", "*", "0"],
+            "@daily" | "@midnight" => vec!["0", "0", "*", "*", "*"],
+            "@hourly" => vec!["0", "*", "*", "*", "*"],
+            expr => expr.split_whitespace().collect(),
+        };
+        if fields.len() != 5 {
+            return None;
+        }
+        let weekdays = parse_field(fields[4], 0, 7, &DAY_NAMES)?;
+
+        Some(Schedule {
+            expr: expr.trim().to_string(),
+            minutes: parse_field(fields[0], 0, 59, &[])?,
+            hours: parse_field(fields[1], 0, 23, &[])?,
+            days: parse_field(fields[2], 1, 31, &[])?,
+            months: parse_field(fields[3], 1, 12, &MONTH_NAMES)?,
+            // 7 is sunday too
+            weekdays: (weekdays | weekdays >> 7) & 0x7f,
+            any_day: fields[2].starts_with('*'),
+            any_weekday: fields[4].starts_with('*'),
+            overlap: Overlap::Skip,
+            zone: Zone::Local,
+            next_run: None,
+            last_run: None,
+            pending: false,
+        })
+    }
+
+    fn day_matches(&self, date: &NaiveDateTime) -> bool {
+        let day = self.days & 1 << date.day() != 0;
+        let weekday = self.weekdays & 1 << date.weekday().num_days_from_sunday() != 0;
+
+        match (self.any_day, self.any_weekday) {
+            (false, false) => day || weekday,
+            _ => day && weekday,
+        }
+    }
+
+    /// First tick strictly after `after`, None when the expression never matches (`0 0 30 2 *`).
+    pub fn next_after(&self, after: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        let start = self.zone.naive(after);
+        let mut date = start.with_second(0)?.with_nanosecond(0)? + Duration::minutes(1);
+
+        while date.year() <= start.year() + 8 {
+            if self.months & 1 << date.month() == 0 {
+                let (year, month) = match date.month() {
+                    12 => (date.year() + 1, 1),
+                    month => (date.year(), month + 1),
+                };
+                date = NaiveDate::from_ymd_opt(year, month, 1)?.and_hms_opt(0, 0, 0)?;
+            } else if !self.day_matches(&date) {
+                date = (date.date() + Duration::days(1)).and_hms_opt(0, 0, 0)?;
+            } else if self.hours & 1 << date.hour() == 0 {
+                date = date.with_minute(0)? + Duration::hours(1);
+            } else if self.minutes & 1 << date.minute() == 0 {
+                date += Duration::minutes(1);
+            } else {
+                match self.zone.utc(&date) {
+                    Some(next) if next > after => return Some(next),
+                    // skipped by a DST change
+                    _ => date += Duration::minutes(1),
+                }
+            }
+        }
+        None
+    }
+
+    fn format(&self, date: Option<DateTime<Utc>>) -> String {
+        date.map(|date| self.zone.format(date))
+            .unwrap_or("never".to_string())
+    }
+}
+
+impl Proc {
+    pub fn setup_schedule(&mut self, parse_file: &File) {
+        self.schedule = parse_file.schedule.clone().map(|mut schedule| {
+            schedule.next_run = schedule.next_after(Utc::now());
+            schedule
+        });
+    }
+
+    // still busy with the previous run
+    fn is_active(&self) -> bool {
+        matches!(
+            self.state,
+            State::STARTING | State::RUNNING | State::STOPPING | State::BACKOFF
+        )
+    }
+
+    /// Start a scheduled process at its tick, or apply its overlap policy.
+    pub fn check_schedule(&mut self) {
+        let mut schedule = match self.schedule.take() {
+            Some(schedule) => schedule,
+            None => return,
+        };
+        let now = Utc::now();
+        let tick = schedule.next_run.map(|next| next <= now) == Some(true);
+
+        if tick {
+            schedule.next_run = schedule.next_after(now);
+        }
+        if tick && (self.is_active() || self.state == State::QUEUED || self.state == State::WAITING)
+        {
+            let date = Local::now().format("%d/%m/%Y %H:%M:%S");
+            match schedule.overlap {
+                Overlap::Skip => println!(
+                    "{} WARN schedule: {} still running, skipping this run",
+                    date,
+                    self.get_name()
+                ),
+                Overlap::Queue => {
+                    println!(
+                        "{} INFO schedule: {} still running, next run starts when it exits",
+                        date,
+                        self.get_name()
+                    );
+                    schedule.pending = true;
+                }
+                Overlap::Kill => {
+                    println!(
+                        "{} WARN schedule: {} still running, stopping it for the new run",
+                        date,
+                        self.get_name()
+                    );
+                    match self.stop() {
+                        Ok(_) => self.restart_on_stop = true,
+                        Err(_) => schedule.pending = true,
+                    }
+                }
+            }
+        } else if (tick || schedule.pending) && !self.is_active() {
+            println!(
+                "{} INFO schedule: starting {} ({})",
+                Local::now().format("%d/%m/%Y %H:%M:%S"),
+                self.get_name(),
+                schedule.expr
+            );
+            schedule.pending = false;
+            schedule.last_run = Some(now);
+            self.nbr_restart = 0;
+            self.enqueue();
+        }
+        self.schedule = Some(schedule);
+    }
+
+    pub fn schedule_description(&self) -> Option<String> {
+        let schedule = self.schedule.as_ref()?;
+
+        Some(format!(
+            "last run {}, next run {}",
+            schedule.format(schedule.last_run),
+            schedule.format(schedule.next_run)
+        ))
+    }
+
+    pub fn schedule_details(&self) -> Vec<(String, String)> {
+        let schedule = match &self.schedule {
+            Some(schedule) => schedule,
+            None => return vec![],
+        };
+
+        vec![
+            (
+                "schedule".to_string(),
+                format!(
+                    "{} (overlap: {})",
+                    schedule.expr,
+                    format!("{:?}", schedule.overlap).to_lowercase()
+                ),
+            ),
+            ("last run".to_string(), schedule.format(schedule.last_run)),
+            ("next run".to_string(), schedule.format(schedule.next_run)),
+        ]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn utc(date: &str) -> DateTime<Utc> {
+        date.parse().unwrap()
+    }
+
+    fn paris(expr: &str) -> Schedule {
+        let mut schedule = Schedule::parse(expr).unwrap();
+        schedule.zone = Zone::Tz("Europe/Paris".parse().unwrap());
+        schedule
+    }
+
+    #[test]
+    fn parse_field_forms() {
+        assert_eq!(parse_field("*", 0, 3, &[]), Some(0b1111));
+        assert_eq!(parse_field("5", 0, 59, &[]), Some(1 << 5));
+        assert_eq!(parse_field("1-3", 0, 59, &[]), Some(0b1110));
+        assert_eq!(parse_field("*/15", 0, 59, &[]), Some(1 | 1 << 15 | 1 << 30 | 1 << 45));
+        assert_eq!(parse_field("10-30/10", 0, 59, &[]), Some(1 << 10 | 1 << 20 | 1 << 30));
+        assert_eq!(parse_field("50/5", 0, 59, &[]), Some(1 << 50 | 1 << 55));
+        assert_eq!(parse_field("1,15", 1, 31, &[]), Some(1 << 1 | 1 << 15));
+        assert_eq!(parse_field("mon-fri", 0, 7, &DAY_NAMES), Some(0b111110));
+        assert_eq!(parse_field("Jan,DEC", 1, 12, &MONTH_NAMES), Some(1 << 1 | 1 << 12));
+    }
+
+    #[test]
+    fn parse_field_rejects() {
+        assert_eq!(parse_field("60", 0, 59, &[]), None);
+        assert_eq!(parse_field("0", 1, 31, &[]), None);
+        assert_eq!(parse_field("5-1", 0, 59, &[]), None);
+        assert_eq!(parse_field("*/0", 0, 59, &[]), None);
+        assert_eq!(parse_field("*/x", 0, 59, &[]), None);
+        assert_eq!(parse_field("", 0, 59, &[]), None);
+        assert_eq!(parse_field("foo", 0, 7, &DAY_NAMES), None);
+    }
+
+    #[test]
+    fn parse_expressions() {
+        assert!(Schedule::parse("@daily").is_some());
+        assert!(Schedule::parse("*/5 * * * *").is_some());
+        assert!(Schedule::parse("* * * *").is_none());
+        assert!(Schedule::parse("* * * * * *").is_none());
+        // 7 is sunday too
+        assert_eq!(Schedule::parse("0 0 * * 7").unwrap().weekdays, 1);
+    }
+
+    #[test]
+    fn next_after_steps() {
+        let schedule = paris("*/15 * * * *");
+        assert_eq!(
+            schedule.next_after(utc("2026-01-10T10:07:30Z")),
+            Some(utc("2026-01-10T10:15:00Z"))
+        );
+        // strictly after
+        assert_eq!(
+            schedule.next_after(utc("2026-01-10T10:15:00Z")),
+            Some(utc("2026-01-10T10:30:00Z"))
+        );
+        // 9:00 in Paris is 8:00 UTC in winter, across the end of the month
+        assert_eq!(
+            paris("0 9 1 * *").next_after(utc("2026-01-31T12:00:00Z")),
+            Some(utc("2026-02-01T08:00:00Z"))
+        );
+    }
+
+    #[test]
+    fn next_after_day_or_weekday() {
+        // the 13th or any friday: friday 2026-01-16 comes before the 13th of february
+        let schedule = paris("0 12 13 * fri");
+        assert_eq!(
+            schedule.next_after(utc("2026-01-14T00:00:00Z")),
+            Some(utc("2026-01-16T11:00:00Z"))
+        );
+    }
+
+    #[test]
+    fn next_after_never() {
+        assert_eq!(Schedule::parse("0 0 30 2 *").unwrap().next_after(Utc::now()), None);
+    }
+
+    #[test]
+    fn next_after_dst_gap() {
+        // 2:30 does not exist on 2026-03-29 in Paris, the next one is the day after
+        assert_eq!(
+            paris("30 2 * * *").next_after(utc("2026-03-29T00:00:00Z")),
+            Some(utc("2026-03-30T00:30:00Z"))
+        );
+    }
+
+    #[test]
+    fn next_after_dst_overlap() {
+        // 2:30 happens twice on 2026-10-25 in Paris, it runs on the first one
+        assert_eq!(
+            paris("30 2 * * *").next_after(utc("2026-10-24T23:00:00Z")),
+            Some(utc("2026-10-25T00:30:00Z"))
+        );
+    }
+}
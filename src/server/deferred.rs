@@ -0,0 +1,246 @@
+use bincode::{deserialize, serialize};
+use chrono::{Local, TimeZone};
+use serde::{Deserialize, Serialize};
+use std::fs::{self, DirBuilder, OpenOptions};
+use std::io::Write;
+use std::os::unix::fs::{DirBuilderExt, OpenOptionsExt};
+use std::path::Path;
+use std::time::Duration;
+
+use super::conf::Taskmaster;
+use super::runtime::check_private;
+use super::tool::DurationDate;
+use share_structures::{CallOn, Response};
+
+// by default in a directory of its own next to the config file
+const STATE_DIR: &str = ".taskmaster";
+const STATE_FILE: &str = "deferred.state";
+
+/// `<command> --at` / `--in`: run by the supervisor once due.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DeferredAction {
+    pub id: u64,
+    // unix seconds
+    pub at: i64,
+    pub cmd: CallOn,
+    pub line: String,
+}
+
+/// What the state file holds: the ids are never handed out twice, even once
+/// the actions that had them are gone.
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct DeferredState {
+    next_id: u64,
+    actions: Vec<DeferredAction>,
+}
+
+pub fn default_state_file(config_path: &str) -> String {
+    let dir = Path::new(config_path)
+        .parent()
+        .filter(|dir| !dir.as_os_str().is_empty())
+        .unwrap_or(Path::new("."));
+    dir.join(STATE_DIR).join(STATE_FILE).to_string_lossy().to_string()
+}
+
+impl DeferredAction {
+    fn describe(&self) -> String {
+        let now = Local::now().timestamp();
+        let when = match Local.timestamp_opt(self.at, 0).single() {
+            Some(at) => at.format("%d/%m/%Y %H:%M:%S").to_string(),
+            None => self.at.to_string(),
+        };
+
+        match self.at > now {
+            true => format!(
+                "#{} `{}` at {} (in {})",
+                self.id,
+                self.line,
+                when,
+                Duration::from_secs((self.at - now) as u64).durationdate()
+            ),
+            false => format!("#{} `{}` at {} (due)", self.id, self.line, when),
+        }
+    }
+}
+
+impl Taskmaster {
+    /// Pick up the actions left in the state file by an earlier run, the
+    /// overdue ones run on the next tick.
+    pub fn load_deferred(&mut self) {
+        let path = Path::new(&self.state_file);
+        if !path.exists() {
+            // nothing deferred yet
+            return;
+        }
+        let checked = path
+            .parent()
+            .map_or(Ok(()), check_private)
+            .and_then(|_| check_private(path));
+        if let Err(e) = checked {
+            println!("refusing deferred actions from {}: {e}", self.state_file);
+            return;
+        }
+        let state = fs::read(path)
+            .map_err(|e| format!("{e}"))
+            .and_then(|data| deserialize::<DeferredState>(&data).map_err(|e| format!("{e}")));
+        match state {
+            Ok(state) => {
+                for action in &state.actions {
+                    println!("INFO deferred: restored {}", action.describe());
+                }
+                self.next_deferred_id = state.next_id.max(1);
+                self.deferred = state.actions;
+            }
+            Err(e) => println!("can't read deferred actions from {}: {e}", self.state_file),
+        }
+    }
+
+    pub fn save_deferred(&self) {
+        let state = DeferredState {
+            next_id: self.next_deferred_id,
+            actions: self.deferred.clone(),
+        };
+        let path = Path::new(&self.state_file);
+        let tmp = format!("{}.tmp", self.state_file);
+        let res = match path.parent() {
+            Some(dir) => DirBuilder::new()
+                .recursive(true)
+                .mode(0o700)
+                .create(dir)
+                .map_err(|e| format!("{e}"))
+                .and_then(|_| check_private(dir)),
+            None => Ok(()),
+        }
+        .and_then(|_| serialize(&state).map_err(|e| format!("{e}")))
+        .and_then(|data| {
+            // written aside then renamed over, never through a planted link
+            let _ = fs::remove_file(&tmp);
+            OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .mode(0o600)
+                .open(&tmp)
+                .and_then(|mut file| file.write_all(&data))
+                .and_then(|_| fs::rename(&tmp, path))
+                .map_err(|e| format!("{e}"))
+        });
+
+        if let Err(e) = res {
+            println!("can't save deferred actions to {}: {e}", self.state_file);
+        }
+    }
+
+    pub fn defer(&mut self, cmd: CallOn, at: i64, line: String) -> Response {
+        let action = DeferredAction {
+            id: self.next_deferred_id,
+            at,
+            cmd: cmd.clone(),
+            line,
+        };
+        self.next_deferred_id += 1;
+        let res = Ok(format!("scheduled {}", action.describe()));
+        let line = action.line.clone();
+
+        println!("INFO deferred: {}", action.describe());
+        self.deferred.push(action);
+        self.save_deferred();
+        Response {
+            cmd: CallOn::Deferred(Box::new(cmd), at, line),
+            content: self.get_proc_list(),
+            success: res,
+        }
+    }
+
+    pub fn pending(&mut self) -> Response {
+        let mut actions = self.deferred.clone();
+        actions.sort_by_key(|action| action.at);
+        let lines: Vec<String> = actions.iter().map(|action| action.describe()).collect();
+
+        Response {
+            cmd: CallOn::Pending,
+            content: self.get_proc_list(),
+            success: match lines.is_empty() {
+                true => Ok("no pending action".to_string()),
+                false => Ok(lines.join("\n\r")),
+            },
+        }
+    }
+
+    pub fn cancel(&mut self, id: u64) -> Response {
+        let res = match self.deferred.iter().position(|action| action.id == id) {
+            Some(index) => {
+                let action = self.deferred.remove(index);
+                self.save_deferred();
+                println!("INFO deferred: cancelled {}", action.describe());
+                Ok(format!("cancelled {}", action.describe()))
+            }
+            None => Err(format!("#{}: ERROR (no such pending action)", id)),
+        };
+
+        Response {
+            cmd: CallOn::Cancel(id),
+            content: self.get_proc_list(),
+            success: res,
+        }
+    }
+
+    // what a client request would do, without waiting on the result
+    fn apply(&mut self, cmd: CallOn) -> Result<String, String> {
+        match cmd {
+            CallOn::Start(ref name) | CallOn::Stop(ref name) | CallOn::Restart(ref name)
+                if self.get_proc_by_name(name.clone()).is_err() =>
+            {
+                Err(format!("{}: ERROR (no such process)", name))
+            }
+            CallOn::Start(name) => self.start(name).success,
+            CallOn::Stop(name) => self.stop(name).success,
+            CallOn::Restart(name) => self.restart(name).success,
+            CallOn::Reload => self.reload().success,
+            CallOn::ReloadApp(program) => self.reload_app(program).success,
+            CallOn::Run(job, _) => self.run(job, false).success,
+            CallOn::RollingRestart(program, batch) => self.rolling_restart(program, batch).success,
+            CallOn::Replace(name) => self.replace(name).success,
+            CallOn::Scale(program, numprocs, save) => self.scale(program, numprocs, save).success,
+            other => Err(format!("{:?} can't be deferred", other)),
+        }
+    }
+
+    /// Run the deferred actions that are due.
+    pub fn run_deferred(&mut self) {
+        let now = Local::now().timestamp();
+        let (due, pending): (Vec<DeferredAction>, Vec<DeferredAction>) =
+            self.deferred.drain(..).partition(|action| action.at <= now);
+
+        self.deferred = pending;
+        if due.is_empty() {
+            return;
+        }
+        self.save_deferred();
+        for action in due {
+            let late = match now - action.at {
+                late if late > 60 => {
+                    format!(", {} late", Duration::from_secs(late as u64).durationdate())
+                }
+                _ => String::new(),
+            };
+            match self.apply(action.cmd) {
+                Ok(res) => println!(
+                    "{} INFO deferred: #{} `{}`{}: {}",
+                    Local::now().format("%d/%m/%Y %H:%M:%S"),
+                    action.id,
+                    action.line,
+                    late,
+                    res.replace("\n\r", ", ")
+                ),
+                Err(e) => println!(
+                    "{} WARN deferred: #{} `{}`{} failed: {}",
+                    Local::now().format("%d/%m/%Y %H:%M:%S"),
+                    action.id,
+                    action.line,
+                    late,
+                    e.replace("\n\r", ", ")
+                ),
+            }
+        }
+    }
+}
@@ -0,0 +1,37 @@
+use super::conf::Taskmaster;
+use super::proc::Proc;
+use share_structures::{CallOn, Response};
+
+impl Proc {
+    /// `status <process>`: one (key, value) per line.
+    pub fn details(&self) -> Vec<(String, String)> {
+        let mut details = vec![
+            ("name".to_string(), self.get_name()),
+            ("state".to_string(), format!("{:?}", self.state)),
+            ("description".to_string(), self.get_current_description()),
+        ];
+
+        details.extend(self.sched_details());
+        details.extend(self.schedule_details());
+        details.extend(self.exit_details());
+        details
+    }
+}
+
+impl Taskmaster {
+    pub fn details(&mut self, name: String) -> Response {
+        let res = self.get_proc_by_name(name.clone()).map(|proc| {
+            proc.details()
+                .iter()
+                .map(|(key, value)| format!("{:<16}{}", key, value))
+                .collect::<Vec<String>>()
+                .join("\n")
+        });
+
+        Response {
+            cmd: CallOn::Details(name),
+            content: self.get_proc_list(),
+            success: res,
+        }
+    }
+}
@@ -0,0 +1,133 @@
+use chrono::{DateTime, Local};
+use nix::sys::signal::Signal;
+use std::fmt;
+use std::os::unix::process::ExitStatusExt;
+use std::process::ExitStatus;
+
+use super::proc::Proc;
+use super::rusage::Usage;
+use share_structures::State;
+
+// runs kept in the exit history of each process
+const EXIT_HISTORY: usize = 10;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ExitReason {
+    Code(i32),
+    Signal(i32),
+    // SIGSYS from the seccomp filter of the program
+    Seccomp,
+    // the cgroup of the process recorded an oom_kill
+    Oom,
+    // stop, restart or shutdown asked to taskmaster
+    Stopped,
+    // stopped for running longer than its timeout
+    Timeout,
+}
+
+impl ExitReason {
+    pub fn from_status(status: ExitStatus, seccomp: bool, oom_killed: bool, stopped: bool) -> Self {
+        match (status.code(), status.signal()) {
+            _ if oom_killed => ExitReason::Oom,
+            (None, Some(signal)) if seccomp && signal == Signal::SIGSYS as i32 => {
+                ExitReason::Seccomp
+            }
+            _ if stopped => ExitReason::Stopped,
+            (Some(code), _) => ExitReason::Code(code),
+            (None, Some(signal)) => ExitReason::Signal(signal),
+            (None, None) => ExitReason::Code(-1),
+        }
+    }
+}
+
+impl fmt::Display for ExitReason {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ExitReason::Code(code) => write!(f, "exit code {}", code),
+            ExitReason::Signal(signal) => match Signal::try_from(*signal) {
+                Ok(signal) => write!(f, "killed by {}", signal),
+                Err(_) => write!(f, "killed by signal {}", signal),
+            },
+            ExitReason::Seccomp => write!(f, "killed by seccomp (SIGSYS)"),
+            ExitReason::Oom => write!(f, "killed by the OOM killer"),
+            ExitReason::Stopped => write!(f, "stopped by taskmaster"),
+            ExitReason::Timeout => write!(f, "stopped by its timeout"),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ExitRecord {
+    pub date: DateTime<Local>,
+    pub reason: ExitReason,
+    pub status: ExitStatus,
+    pub usage: Option<Usage>,
+}
+
+impl fmt::Display for ExitRecord {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} {}",
+            self.date.format("%d/%m/%Y %H:%M:%S"),
+            self.reason
+        )?;
+        if matches!(
+            self.reason,
+            ExitReason::Oom | ExitReason::Stopped | ExitReason::Timeout
+        ) {
+            write!(f, " ({})", self.status)?;
+        }
+        match &self.usage {
+            Some(usage) => write!(f, ", {}", usage.to_details()),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Proc {
+    /// Keep the reason of the exit in the history, after the process has been reaped.
+    pub fn record_exit(&mut self, status: ExitStatus, oom_killed: bool) {
+        let stopped = self.state == State::STOPPED;
+        let reason = match ExitReason::from_status(status, self.seccomp, oom_killed, stopped) {
+            ExitReason::Stopped if self.timed_out => ExitReason::Timeout,
+            reason => reason,
+        };
+
+        if reason == ExitReason::Seccomp {
+            println!(
+                "{} WARN seccomp: {} was killed for a forbidden syscall (SIGSYS)",
+                Local::now().format("%d/%m/%Y %H:%M:%S"),
+                self.get_name()
+            );
+            self.description = format!("{}, {}", reason, self.description);
+        }
+        if self.exit_history.len() == EXIT_HISTORY {
+            self.exit_history.pop_front();
+        }
+        self.exit_history.push_back(ExitRecord {
+            date: Local::now(),
+            reason,
+            status,
+            usage: self.last_usage(),
+        });
+    }
+
+    /// How the last run ended: its spawn error, else its exit reason.
+    pub fn last_outcome(&self) -> String {
+        match self.exit_history.back() {
+            // the last exit is from an older run
+            _ if self.description.starts_with("spawnerr") => self.description.clone(),
+            Some(exit) => exit.reason.to_string(),
+            None => self.description.clone(),
+        }
+    }
+
+    pub fn exit_details(&self) -> Vec<(String, String)> {
+        self.exit_history
+            .iter()
+            .rev()
+            .map(|exit| ("exit".to_string(), exit.to_string()))
+            .collect()
+    }
+}
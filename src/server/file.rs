@@ -1,6 +1,6 @@
 use chrono::Local;
 use sha1::{Digest, Sha1};
-use std::{fs::File, process::Command};
+use std::fs::File;
 use yaml_rust::Yaml;
 
 use super::{
@@ -172,7 +172,7 @@ impl Task {
         }
         filelog.name = Some(n_name.clone());
         Proc::new(
-            Command::new(self.getpathref()),
+            self.new_command(),
             filelog.clone(),
             Some(n_name),
         )
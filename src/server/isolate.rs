@@ -0,0 +1,314 @@
+use nix::libc;
+use std::ffi::CString;
+use std::io;
+use std::os::unix::process::CommandExt;
+use std::sync::atomic::{AtomicI32, Ordering};
+use yaml_rust::Yaml;
+
+use super::parse::File;
+use super::proc::Proc;
+
+const NAMESPACES: [(&str, libc::c_int); 5] = [
+    ("mount", libc::CLONE_NEWNS),
+    ("network", libc::CLONE_NEWNET),
+    ("pid", libc::CLONE_NEWPID),
+    ("ipc", libc::CLONE_NEWIPC),
+    ("uts", libc::CLONE_NEWUTS),
+];
+
+// signals a stop or a reload can use, relayed down to the program
+const FORWARDED: [libc::c_int; 9] = [
+    libc::SIGHUP,
+    libc::SIGINT,
+    libc::SIGQUIT,
+    libc::SIGUSR1,
+    libc::SIGUSR2,
+    libc::SIGTERM,
+    libc::SIGALRM,
+    libc::SIGWINCH,
+    libc::SIGCONT,
+];
+
+// pid the signals are relayed to, set in each forked relay
+static FORWARD_TO: AtomicI32 = AtomicI32::new(0);
+
+extern "C" fn forward_signal(signal: libc::c_int) {
+    let pid = FORWARD_TO.load(Ordering::Relaxed);
+
+    if pid > 0 {
+        unsafe {
+            libc::kill(pid, signal);
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Isolate {
+    // CLONE_NEW* flags given to unshare
+    pub flags: libc::c_int,
+    pub chroot: Option<String>,
+}
+
+impl Isolate {
+    /// `isolate: [mount, network, pid, ipc, uts]` (or `all`) and `chroot: <dir>`.
+    pub fn from_yaml(yaml_file: &Yaml) -> Option<Self> {
+        let names: Vec<&str> = match &yaml_file["isolate"] {
+            Yaml::String(name) => vec![name.as_str()],
+            Yaml::Array(names) => names.iter().filter_map(|name| name.as_str()).collect(),
+            _ => vec![],
+        };
+        let mut flags = 0;
+
+        for name in names {
+            match NAMESPACES.iter().find(|(namespace, _)| *namespace == name) {
+                Some((_, flag)) => flags |= flag,
+                None if name == "all" => NAMESPACES.iter().for_each(|(_, flag)| flags |= flag),
+                None => println!("isolate: ignoring unknown namespace {}", name),
+            }
+        }
+        let chroot = yaml_file["chroot"].as_str().map(str::to_string);
+
+        match (flags, &chroot) {
+            (0, None) => None,
+            _ => Some(Isolate { flags, chroot }),
+        }
+    }
+
+    pub fn has(&self, flag: libc::c_int) -> bool {
+        self.flags & flag != 0
+    }
+
+    pub fn namespaces(&self) -> Vec<&'static str> {
+        NAMESPACES
+            .iter()
+            .filter(|(_, flag)| self.has(*flag))
+            .map(|(name, _)| *name)
+            .collect()
+    }
+}
+
+fn check(res: libc::c_int) -> io::Result<()> {
+    match res {
+        -1 => Err(io::Error::last_os_error()),
+        _ => Ok(()),
+    }
+}
+
+unsafe fn close_fds_except(keep: libc::c_int) {
+    libc::syscall(libc::SYS_close_range, 3, keep - 1, 0);
+    libc::syscall(libc::SYS_close_range, keep + 1, libc::c_uint::MAX, 0);
+}
+
+unsafe fn forward_signals_to(pid: libc::pid_t) {
+    FORWARD_TO.store(pid, Ordering::Relaxed);
+    for signal in FORWARDED {
+        libc::signal(signal, forward_signal as *const () as libc::sighandler_t);
+    }
+}
+
+// wait status of `pid`, retrying on the relayed signals
+unsafe fn wait_for(pid: libc::pid_t) -> Option<libc::c_int> {
+    let mut status = 0;
+
+    loop {
+        match libc::waitpid(pid, &mut status, 0) {
+            -1 if io::Error::last_os_error().raw_os_error() == Some(libc::EINTR) => continue,
+            -1 => return None,
+            _ => return Some(status),
+        }
+    }
+}
+
+// end this process the way the program ended
+unsafe fn exit_like(status: libc::c_int) -> ! {
+    if libc::WIFSIGNALED(status) {
+        let signal = libc::WTERMSIG(status);
+        libc::signal(signal, libc::SIG_DFL);
+        libc::kill(libc::getpid(), signal);
+        libc::_exit(128 + signal);
+    }
+    libc::_exit(libc::WEXITSTATUS(status));
+}
+
+// pid 1 of the namespace: reap orphans, relay signals, report how the program ended
+unsafe fn run_init(program: libc::pid_t, report: libc::c_int) -> ! {
+    close_fds_except(report);
+    forward_signals_to(program);
+    loop {
+        let mut status = 0;
+        match libc::waitpid(-1, &mut status, 0) {
+            pid if pid == program => {
+                libc::write(
+                    report,
+                    &status as *const libc::c_int as *const libc::c_void,
+                    std::mem::size_of::<libc::c_int>(),
+                );
+                libc::_exit(0);
+            }
+            -1 if io::Error::last_os_error().raw_os_error() != Some(libc::EINTR) => libc::_exit(1),
+            _ => {}
+        }
+    }
+}
+
+// the process taskmaster tracks stays outside of the pid namespace and mirrors its init
+unsafe fn run_relay(init: libc::pid_t, report: libc::c_int) -> ! {
+    close_fds_except(report);
+    forward_signals_to(init);
+    let mut status: libc::c_int = 0;
+    let len = loop {
+        match libc::read(
+            report,
+            &mut status as *mut libc::c_int as *mut libc::c_void,
+            std::mem::size_of::<libc::c_int>(),
+        ) {
+            -1 if io::Error::last_os_error().raw_os_error() == Some(libc::EINTR) => continue,
+            len => break len,
+        }
+    };
+    let init_status = wait_for(init).unwrap_or(0);
+
+    match len == std::mem::size_of::<libc::c_int>() as isize {
+        true => exit_like(status),
+        // init died before the program, e.g. SIGKILL
+        false => exit_like(init_status),
+    }
+}
+
+fn loopback_up() -> io::Result<()> {
+    unsafe {
+        let sock = libc::socket(libc::AF_INET, libc::SOCK_DGRAM | libc::SOCK_CLOEXEC, 0);
+        check(sock)?;
+        let mut ifreq: libc::ifreq = std::mem::zeroed();
+        for (i, c) in b"lo".iter().enumerate() {
+            ifreq.ifr_name[i] = *c as libc::c_char;
+        }
+        let res = match libc::ioctl(sock, libc::SIOCGIFFLAGS, &mut ifreq) {
+            -1 => -1,
+            _ => {
+                ifreq.ifr_ifru.ifru_flags |= libc::IFF_UP as libc::c_short;
+                libc::ioctl(sock, libc::SIOCSIFFLAGS, &ifreq)
+            }
+        };
+        let err = io::Error::last_os_error();
+        libc::close(sock);
+        match res {
+            -1 => Err(err),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl Proc {
+    pub fn setup_command_isolate(&mut self, parse_file: &File) {
+        let isolate = match &parse_file.isolate {
+            Some(isolate) => isolate.clone(),
+            None => return,
+        };
+        let labels = (
+            format!("unshare namespaces {:?}", isolate.namespaces()),
+            "fork the init of the pid namespace".to_string(),
+            "bring up loopback".to_string(),
+            "mount /proc".to_string(),
+            "bind the private tmp on /tmp".to_string(),
+            format!("chroot to {}", isolate.chroot.clone().unwrap_or_default()),
+        );
+        let chroot = isolate
+            .chroot
+            .as_ref()
+            .map(|dir| CString::new(dir.as_str()).expect("chroot with a nul byte"));
+        // (source, target) of the private_tmp bind mount, inside the chroot if any,
+        // the source is a new directory for each start
+        let private_tmp = match &self.private_tmp {
+            Some(tmp) if tmp.on_tmp => Some((
+                tmp.source.clone(),
+                CString::new(format!(
+                    "{}/tmp",
+                    isolate
+                        .chroot
+                        .as_deref()
+                        .unwrap_or("")
+                        .trim_end_matches('/')
+                ))
+                .expect("chroot with a nul byte"),
+            )),
+            _ => None,
+        };
+        let root = CString::new("/").expect("no nul byte");
+        let proc_fs = CString::new("proc").expect("no nul byte");
+        let proc_dir = CString::new("/proc").expect("no nul byte");
+        let step = self.spawn_step.clone();
+
+        if let Some(command) = &mut self.command {
+            unsafe {
+                command.pre_exec(move || {
+                    if isolate.flags != 0 {
+                        step.set(&labels.0);
+                        check(libc::unshare(isolate.flags))?;
+                    }
+                    if isolate.has(libc::CLONE_NEWNS) {
+                        // keep our mounts out of the host
+                        step.set(&labels.0);
+                        check(libc::mount(
+                            std::ptr::null(),
+                            root.as_ptr(),
+                            std::ptr::null(),
+                            libc::MS_REC | libc::MS_PRIVATE,
+                            std::ptr::null(),
+                        ))?;
+                    }
+                    if let Some((source, target)) = &private_tmp {
+                        step.set(&labels.4);
+                        check(libc::mount(
+                            source.as_ptr(),
+                            target.as_ptr(),
+                            std::ptr::null(),
+                            libc::MS_BIND | libc::MS_REC,
+                            std::ptr::null(),
+                        ))?;
+                    }
+                    if isolate.has(libc::CLONE_NEWPID) {
+                        // only the children of this process enter the namespace
+                        step.set(&labels.1);
+                        let mut report = [0; 2];
+                        check(libc::pipe2(report.as_mut_ptr(), libc::O_CLOEXEC))?;
+                        let init = libc::fork();
+                        check(init)?;
+                        if init > 0 {
+                            libc::close(report[1]);
+                            run_relay(init, report[0]);
+                        }
+                        libc::close(report[0]);
+                        libc::prctl(libc::PR_SET_PDEATHSIG, libc::SIGKILL, 0, 0, 0);
+                        let program = libc::fork();
+                        check(program)?;
+                        if program > 0 {
+                            run_init(program, report[1]);
+                        }
+                        libc::prctl(libc::PR_SET_PDEATHSIG, libc::SIGKILL, 0, 0, 0);
+                        if isolate.has(libc::CLONE_NEWNS) {
+                            step.set(&labels.3);
+                            check(libc::mount(
+                                proc_fs.as_ptr(),
+                                proc_dir.as_ptr(),
+                                proc_fs.as_ptr(),
+                                libc::MS_NOSUID | libc::MS_NODEV | libc::MS_NOEXEC,
+                                std::ptr::null(),
+                            ))?;
+                        }
+                    }
+                    if isolate.has(libc::CLONE_NEWNET) {
+                        step.set(&labels.2);
+                        loopback_up()?;
+                    }
+                    if let Some(dir) = &chroot {
+                        step.set(&labels.5);
+                        check(libc::chroot(dir.as_ptr()))?;
+                        check(libc::chdir(root.as_ptr()))?;
+                    }
+                    Ok(())
+                });
+            }
+        }
+    }
+}
@@ -0,0 +1,177 @@
+use chrono::Local;
+use std::process::ExitStatus;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use super::conf::Taskmaster;
+use super::exit::ExitReason;
+use super::proc::Proc;
+use super::tool::DurationDate;
+use share_structures::{CallOn, Response, State};
+
+// how often `run --wait` looks at the job
+const WAIT_POLL: Duration = Duration::from_millis(200);
+
+impl Proc {
+    /// After a run of a job: SUCCEEDED for a oneshot exiting with one of its
+    /// exitcodes, FATAL for a run killed by its timeout.
+    pub fn finish_job(&mut self, status: ExitStatus) {
+        if std::mem::take(&mut self.timed_out) {
+            self.description = format!(
+                "timed out after {}",
+                self.timeout.unwrap_or_default().durationdate()
+            );
+            self.change_to_fatal();
+            return;
+        }
+        let expected = status
+            .code()
+            .map(|code| self.exp_exit.contains(&code))
+            .unwrap_or(false);
+
+        if self.oneshot && self.state == State::EXITED && expected {
+            self.change_state(State::SUCCEEDED);
+        }
+    }
+
+    /// Stop a run that lasts longer than `timeout`.
+    pub fn check_timeout(&mut self) {
+        let timeout = match self.timeout {
+            Some(timeout) => timeout,
+            None => return,
+        };
+        let elapsed = self.spawned_time.map(|time| time.elapsed());
+
+        if self.state == State::RUNNING && elapsed.map(|e| e > timeout) == Some(true) {
+            println!(
+                "{} WARN timeout: {} still running after {}, stopping it",
+                Local::now().format("%d/%m/%Y %H:%M:%S"),
+                self.get_name(),
+                timeout.durationdate()
+            );
+            if self.stop().is_ok() {
+                self.timed_out = true;
+            }
+        }
+    }
+
+    fn job_done(&self) -> bool {
+        matches!(
+            self.state,
+            State::SUCCEEDED | State::EXITED | State::FATAL | State::STOPPED
+        )
+    }
+
+    // (line for `run --wait`, whether the run went well)
+    fn job_result(&self) -> (String, bool) {
+        let reason = self.exit_history.back().map(|exit| &exit.reason);
+        let ok = match (&self.state, reason) {
+            (State::SUCCEEDED, _) => true,
+            (State::EXITED, Some(ExitReason::Code(code))) => self.exp_exit.contains(code),
+            _ => false,
+        };
+
+        (
+            format!(
+                "{}: {:?} ({})",
+                self.get_name(),
+                self.state,
+                self.last_outcome()
+            ),
+            ok,
+        )
+    }
+}
+
+impl Taskmaster {
+    // every process of the program `name`, or the process `name`
+    fn job_names(&self, name: &str) -> Vec<String> {
+        match self
+            .task_lst
+            .iter()
+            .find(|task| task.getnameparse() == name)
+        {
+            Some(task) => task
+                .process_lst
+                .iter()
+                .map(|proc| proc.get_name())
+                .collect(),
+            None => self
+                .task_lst
+                .iter()
+                .flat_map(|task| task.process_lst.iter())
+                .filter(|proc| proc.get_name() == name)
+                .map(|proc| proc.get_name())
+                .collect(),
+        }
+    }
+
+    /// `run <job>`: start every process of the job now.
+    pub fn run(&mut self, name: String, wait: bool) -> Response {
+        let names = self.job_names(&name);
+        let res = match names.is_empty() {
+            true => Err(format!("{}: ERROR (no such job)", name)),
+            false => {
+                let mut lines = vec![];
+                let mut ok = true;
+                for proc_name in names {
+                    let started = match self.running_conflict(&proc_name) {
+                        Some(other) => Err(format!(
+                            "{}: ERROR (conflicts with {}, which is running)",
+                            proc_name, other
+                        )),
+                        None => self.get_proc_by_name(proc_name).unwrap().start(),
+                    };
+                    ok &= started.is_ok();
+                    lines.push(started.unwrap_or_else(|e| e));
+                }
+                match ok {
+                    true => Ok(lines.join("\n\r")),
+                    false => Err(lines.join("\n\r")),
+                }
+            }
+        };
+
+        Response {
+            cmd: CallOn::Run(name, wait),
+            content: self.get_proc_list(),
+            success: res,
+        }
+    }
+
+    // None while a process of the job is still running
+    fn job_results(&mut self, name: &str) -> Option<Result<String, String>> {
+        let mut lines = vec![];
+        let mut ok = true;
+
+        for proc_name in self.job_names(name) {
+            let proc = self.get_proc_by_name(proc_name).ok()?;
+            if !proc.job_done() {
+                return None;
+            }
+            let (line, proc_ok) = proc.job_result();
+            lines.push(line);
+            ok &= proc_ok;
+        }
+        match ok {
+            true => Some(Ok(lines.join("\n\r"))),
+            false => Some(Err(lines.join("\n\r"))),
+        }
+    }
+}
+
+/// `run --wait <job>`: hold the client until every process of the job is done.
+pub fn wait_job(taskmaster: &Arc<Mutex<Taskmaster>>, name: String) -> Response {
+    loop {
+        thread::sleep(WAIT_POLL);
+        let mut app = taskmaster.lock().unwrap();
+        if let Some(res) = app.job_results(&name) {
+            return Response {
+                cmd: CallOn::Run(name, true),
+                content: app.get_proc_list(),
+                success: res,
+            };
+        }
+    }
+}
@@ -0,0 +1,290 @@
+use chrono::Local;
+use nix::libc;
+use std::cell::UnsafeCell;
+use std::ffi::CString;
+use std::fs;
+use std::io;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use yaml_rust::Yaml;
+
+use super::parse::File;
+use super::proc::Proc;
+
+const LANDLOCK_CREATE_RULESET_VERSION: libc::c_uint = 1;
+const LANDLOCK_RULE_PATH_BENEATH: libc::c_int = 1;
+
+const ACCESS_FS_EXECUTE: u64 = 1 << 0;
+const ACCESS_FS_WRITE_FILE: u64 = 1 << 1;
+const ACCESS_FS_READ_FILE: u64 = 1 << 2;
+const ACCESS_FS_READ_DIR: u64 = 1 << 3;
+// remove_dir .. make_sym, the rights of ABI v1
+const ACCESS_FS_V1: u64 = (1 << 13) - 1;
+const ACCESS_FS_REFER: u64 = 1 << 13;
+const ACCESS_FS_TRUNCATE: u64 = 1 << 14;
+const ACCESS_FS_IOCTL_DEV: u64 = 1 << 15;
+
+const ACCESS_READ: u64 = ACCESS_FS_EXECUTE | ACCESS_FS_READ_FILE | ACCESS_FS_READ_DIR;
+// the only rights a rule on a regular file can carry
+const ACCESS_FILE: u64 = ACCESS_FS_EXECUTE
+    | ACCESS_FS_WRITE_FILE
+    | ACCESS_FS_READ_FILE
+    | ACCESS_FS_TRUNCATE
+    | ACCESS_FS_IOCTL_DEV;
+
+#[repr(C)]
+struct RulesetAttr {
+    handled_access_fs: u64,
+}
+
+#[repr(C, packed)]
+struct PathBeneathAttr {
+    allowed_access: u64,
+    parent_fd: i32,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Landlock {
+    pub read_only: Vec<PathBuf>,
+    pub read_write: Vec<PathBuf>,
+    pub no_access: Vec<PathBuf>,
+    // fail the spawn instead of running unconfined when landlock is missing
+    pub strict: bool,
+}
+
+/// Rules of the next spawn for the pre_exec hook: built before the fork,
+/// only read in the child.
+pub struct RulesSource {
+    rules: UnsafeCell<Vec<(CString, u64)>>,
+}
+
+// only written by the thread about to spawn, under the taskmaster lock
+unsafe impl Send for RulesSource {}
+unsafe impl Sync for RulesSource {}
+
+pub struct LandlockRules {
+    pub landlock: Landlock,
+    pub handled: u64,
+    pub source: Arc<RulesSource>,
+}
+
+fn path_list(yaml: &Yaml) -> Vec<PathBuf> {
+    yaml.as_vec()
+        .map(|paths| {
+            paths
+                .iter()
+                .filter_map(|path| path.as_str().map(PathBuf::from))
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Landlock ABI version of the running kernel.
+pub fn landlock_abi() -> io::Result<i64> {
+    match unsafe {
+        libc::syscall(
+            libc::SYS_landlock_create_ruleset,
+            std::ptr::null::<RulesetAttr>(),
+            0,
+            LANDLOCK_CREATE_RULESET_VERSION,
+        )
+    } {
+        abi if abi < 0 => Err(io::Error::last_os_error()),
+        abi => Ok(abi),
+    }
+}
+
+fn handled_access(abi: i64) -> u64 {
+    let mut access = ACCESS_FS_V1;
+
+    if abi >= 2 {
+        access |= ACCESS_FS_REFER;
+    }
+    if abi >= 3 {
+        access |= ACCESS_FS_TRUNCATE;
+    }
+    if abi >= 5 {
+        access |= ACCESS_FS_IOCTL_DEV;
+    }
+    access
+}
+
+// Landlock only grants access: to hide `denied` inside `granted`, grant every
+// entry along the way except the one leading to `denied`.
+fn grant_around(granted: &Path, denied: &Path, access: u64, rules: &mut Vec<(PathBuf, u64)>) {
+    let mut dir = granted.to_path_buf();
+
+    for component in denied.strip_prefix(granted).unwrap_or(Path::new("")) {
+        let entries = fs::read_dir(&dir)
+            .map(|entries| entries.filter_map(|e| e.ok()).collect::<Vec<_>>())
+            .unwrap_or_default();
+        for entry in entries.iter().filter(|e| e.file_name() != component) {
+            rules.push((entry.path(), access));
+        }
+        dir.push(component);
+    }
+}
+
+impl Landlock {
+    pub fn from_yaml(yaml_file: &Yaml) -> Option<Self> {
+        let landlock = Landlock {
+            read_only: path_list(&yaml_file["read_only_paths"]),
+            read_write: path_list(&yaml_file["read_write_paths"]),
+            no_access: path_list(&yaml_file["no_access_paths"]),
+            strict: yaml_file["landlock_strict"].as_bool().unwrap_or(false),
+        };
+
+        match landlock.read_only.is_empty()
+            && landlock.read_write.is_empty()
+            && landlock.no_access.is_empty()
+        {
+            true => None,
+            false => Some(landlock),
+        }
+    }
+
+    /// (path, allowed access) of every rule of the ruleset.
+    pub fn rules(&self, handled: u64) -> Vec<(PathBuf, u64)> {
+        let mut rules: Vec<(PathBuf, u64)> = self
+            .read_only
+            .iter()
+            .map(|path| (path.clone(), ACCESS_READ))
+            .chain(self.read_write.iter().map(|path| (path.clone(), handled)))
+            .collect();
+
+        // only no_access_paths: everything else stays accessible
+        if rules.is_empty() {
+            rules.push((PathBuf::from("/"), handled));
+        }
+        for denied in &self.no_access {
+            let (around, kept): (Vec<_>, Vec<_>) = rules
+                .into_iter()
+                .partition(|(granted, _)| denied.starts_with(granted));
+            rules = kept;
+            for (granted, access) in around {
+                grant_around(&granted, denied, access, &mut rules);
+            }
+        }
+        rules
+    }
+}
+
+impl Proc {
+    pub fn setup_command_landlock(&mut self, parse_file: &File) {
+        self.landlock = None;
+        let landlock = match &parse_file.landlock {
+            Some(landlock) => landlock,
+            None => return,
+        };
+        let step = self.spawn_step.clone();
+        let abi = landlock_abi();
+
+        if let Err(e) = &abi {
+            if !landlock.strict {
+                println!(
+                    "{} WARN landlock: not supported by the kernel ({e}), {} runs without its path restrictions",
+                    Local::now().format("%d/%m/%Y %H:%M:%S"),
+                    self.get_name()
+                );
+                return;
+            }
+        }
+        let abi = abi.map_err(|e| e.raw_os_error().unwrap_or(libc::ENOSYS));
+        let handled = abi.as_ref().map(|abi| handled_access(*abi)).unwrap_or(0);
+        let source = Arc::new(RulesSource {
+            rules: UnsafeCell::new(vec![]),
+        });
+        let rules = source.clone();
+
+        if let Some(command) = &mut self.command {
+            unsafe {
+                command.pre_exec(move || {
+                    step.set("enforce landlock path restrictions");
+                    if let Err(errno) = abi {
+                        return Err(io::Error::from_raw_os_error(errno));
+                    }
+                    let attr = RulesetAttr {
+                        handled_access_fs: handled,
+                    };
+                    let ruleset = libc::syscall(
+                        libc::SYS_landlock_create_ruleset,
+                        &attr as *const RulesetAttr,
+                        std::mem::size_of::<RulesetAttr>(),
+                        0,
+                    ) as libc::c_int;
+                    if ruleset < 0 {
+                        return Err(io::Error::last_os_error());
+                    }
+                    for (path, access) in &*rules.rules.get() {
+                        let fd = libc::open(path.as_ptr(), libc::O_PATH | libc::O_CLOEXEC);
+                        // a missing path grants nothing
+                        if fd < 0 {
+                            continue;
+                        }
+                        let mut stat: libc::stat = std::mem::zeroed();
+                        let is_dir = libc::fstat(fd, &mut stat) == 0
+                            && stat.st_mode & libc::S_IFMT == libc::S_IFDIR;
+                        let rule = PathBeneathAttr {
+                            allowed_access: match is_dir {
+                                true => access & handled,
+                                false => access & handled & ACCESS_FILE,
+                            },
+                            parent_fd: fd,
+                        };
+                        let res = libc::syscall(
+                            libc::SYS_landlock_add_rule,
+                            ruleset,
+                            LANDLOCK_RULE_PATH_BENEATH,
+                            &rule as *const PathBeneathAttr,
+                            0,
+                        );
+                        let err = io::Error::last_os_error();
+                        libc::close(fd);
+                        if res < 0 {
+                            libc::close(ruleset);
+                            return Err(err);
+                        }
+                    }
+                    let res = match libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0) {
+                        0 => libc::syscall(libc::SYS_landlock_restrict_self, ruleset, 0),
+                        err => err as libc::c_long,
+                    };
+                    let err = io::Error::last_os_error();
+                    libc::close(ruleset);
+                    match res {
+                        0 => Ok(()),
+                        _ => Err(err),
+                    }
+                });
+            }
+        }
+        self.landlock = Some(LandlockRules {
+            landlock: landlock.clone(),
+            handled,
+            source,
+        });
+    }
+
+    /// The rules are built at each start: grant_around lists the directories
+    /// as they are then, not as they were when the configuration was read.
+    pub fn prepare_landlock(&mut self) {
+        if let Some(landlock) = &self.landlock {
+            let rules = landlock
+                .landlock
+                .rules(landlock.handled)
+                .into_iter()
+                .filter_map(|(path, access)| {
+                    CString::new(path.as_os_str().as_bytes())
+                        .ok()
+                        .map(|path| (path, access))
+                })
+                .collect();
+
+            unsafe {
+                *landlock.source.rules.get() = rules;
+            }
+        }
+    }
+}
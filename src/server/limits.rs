@@ -0,0 +1,109 @@
+use nix::sys::resource::{rlim_t, setrlimit, Resource, RLIM_INFINITY};
+use std::os::unix::process::CommandExt;
+use yaml_rust::Yaml;
+
+use super::parse::File;
+use super::proc::Proc;
+
+#[derive(Debug, Clone)]
+pub struct Rlimit {
+    pub resource: Resource,
+    pub soft: rlim_t,
+    pub hard: rlim_t,
+    pub label: String,
+}
+
+fn resource_from_name(name: &str) -> Option<Resource> {
+    match name {
+        "as" => Some(Resource::RLIMIT_AS),
+        "core" => Some(Resource::RLIMIT_CORE),
+        "cpu" => Some(Resource::RLIMIT_CPU),
+        "data" => Some(Resource::RLIMIT_DATA),
+        "fsize" => Some(Resource::RLIMIT_FSIZE),
+        "locks" => Some(Resource::RLIMIT_LOCKS),
+        "memlock" => Some(Resource::RLIMIT_MEMLOCK),
+        "msgqueue" => Some(Resource::RLIMIT_MSGQUEUE),
+        "nice" => Some(Resource::RLIMIT_NICE),
+        "nofile" => Some(Resource::RLIMIT_NOFILE),
+        "nproc" => Some(Resource::RLIMIT_NPROC),
+        "rss" => Some(Resource::RLIMIT_RSS),
+        "rtprio" => Some(Resource::RLIMIT_RTPRIO),
+        "rttime" => Some(Resource::RLIMIT_RTTIME),
+        "sigpending" => Some(Resource::RLIMIT_SIGPENDING),
+        "stack" => Some(Resource::RLIMIT_STACK),
+        _ => None,
+    }
+}
+
+fn limit_from_str(value: &str) -> Option<rlim_t> {
+    match value {
+        "unlimited" | "infinity" => Some(RLIM_INFINITY),
+        _ => value.parse().ok(),
+    }
+}
+
+fn limit_to_string(limit: rlim_t) -> String {
+    match limit {
+        RLIM_INFINITY => "unlimited".to_string(),
+        _ => limit.to_string(),
+    }
+}
+
+// `4096` (soft = hard), `"1024:4096"` (soft:hard) or `unlimited`
+fn parse_limit(value: &Yaml) -> Option<(rlim_t, rlim_t)> {
+    if let Some(limit) = value.as_i64() {
+        return Some((limit as rlim_t, limit as rlim_t));
+    }
+    let value = value.as_str()?;
+    match value.split_once(':') {
+        Some((soft, hard)) => Some((limit_from_str(soft)?, limit_from_str(hard)?)),
+        None => limit_from_str(value).map(|limit| (limit, limit)),
+    }
+}
+
+pub fn parse_rlimits(yaml: &Yaml) -> Vec<Rlimit> {
+    let mut rlimits = vec![];
+
+    if let Some(hash) = yaml.as_hash() {
+        for (key, value) in hash {
+            let name = key.as_str().unwrap_or_default();
+            match (resource_from_name(name), parse_limit(value)) {
+                (Some(resource), Some((soft, hard))) => rlimits.push(Rlimit {
+                    resource,
+                    soft,
+                    hard,
+                    label: format!(
+                        "set rlimit {} to {}:{}",
+                        name,
+                        limit_to_string(soft),
+                        limit_to_string(hard)
+                    ),
+                }),
+                _ => println!("rlimits: ignoring invalid limit {}: {:?}", name, value),
+            }
+        }
+    }
+    rlimits
+}
+
+impl Proc {
+    pub fn setup_command_rlimits(&mut self, parse_file: &File) {
+        if parse_file.rlimits.is_empty() {
+            return;
+        }
+        let rlimits = parse_file.rlimits.clone();
+        let step = self.spawn_step.clone();
+
+        if let Some(command) = &mut self.command {
+            unsafe {
+                command.pre_exec(move || {
+                    for limit in &rlimits {
+                        step.set(&limit.label);
+                        setrlimit(limit.resource, limit.soft, limit.hard)?;
+                    }
+                    Ok(())
+                });
+            }
+        }
+    }
+}
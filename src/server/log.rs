@@ -1,3 +1,6 @@
+use chrono::Local;
+use std::time::Duration;
+
 use crate::tool::DurationDate;
 
 use super::proc::Proc;
@@ -70,11 +73,47 @@ impl Proc {
         )
     }
 
-    pub fn spawnerr_log(&self) -> String {
+    pub fn succeeded_log(&self) -> String {
         format!(
-            "{:?} INFO spawerr : can't find command '{}' ",
-            self.sys_time.unwrap(),
-            self.name.as_ref().unwrap()
+            "{} INFO succeeded: {} ran to completion",
+            Local::now().format("%d/%m/%Y %H:%M:%S"),
+            self.get_name()
+        )
+    }
+
+    pub fn spawnerr_log(&self, error: &str) -> String {
+        format!(
+            "{} INFO spawnerr: {}: {}",
+            Local::now().format("%d/%m/%Y %H:%M:%S"),
+            self.get_name(),
+            error
+        )
+    }
+
+    pub fn waiting_log(&self) -> String {
+        format!(
+            "{} INFO waiting: {} start conditions not met ({})",
+            Local::now().format("%d/%m/%Y %H:%M:%S"),
+            self.get_name(),
+            self.description
+        )
+    }
+
+    pub fn queued_log(&self) -> String {
+        format!(
+            "{} INFO queued: {} {}",
+            Local::now().format("%d/%m/%Y %H:%M:%S"),
+            self.get_name(),
+            self.queued_description()
+        )
+    }
+
+    pub fn watchdog_log(&self, elapsed: Duration) -> String {
+        format!(
+            "{} WARN watchdog: {} sent no keepalive for {} seconds, restarting",
+            Local::now().format("%d/%m/%Y %H:%M:%S"),
+            self.get_name(),
+            elapsed.as_secs()
         )
     }
-}
\ No newline at end of file
+}
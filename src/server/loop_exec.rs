@@ -42,7 +42,15 @@ impl LoopRestart for Task {
 
 impl LoopRestart for Proc {
     fn start_loop(&mut self) {
-        let ret = self.command.as_mut().expect("working at this point ? (Command)").spawn().expect("failed to execute");
+        if let Some(reason) = self.unmet_condition() {
+            // does not count as a start retry
+            self.change_to_waiting(reason);
+            return;
+        }
+        let ret = match self.spawn_child() {
+            Ok(child) => child,
+            Err(e) => return self.spawn_failed(e),
+        };
         //need a protection w/ the status of the process ...
         self.child.get_or_insert(ret);
         self.nbr_restart += 1;
@@ -168,6 +176,7 @@ impl Taskmaster {
     pub fn update_and_exec(& mut self) {
         self.update_all_task_method();
         self.all_exec_loop();
+        self.dispatch_queued();
     }
 
     pub fn all_task_stable(& mut self) -> bool {
@@ -176,7 +185,7 @@ impl Taskmaster {
             for j in & mut i.process_lst {
                 match j.state
                 {
-                    State::STOPPED |  State::RUNNING | State::FATAL => {
+                    State::STOPPED |  State::RUNNING | State::FATAL | State::WAITING | State::QUEUED | State::SUCCEEDED => {
                         all_bool &= true;
                     },
                     _ => {
@@ -0,0 +1,186 @@
+use chrono::Local;
+use std::fs;
+use std::time::{Duration, Instant};
+use yaml_rust::Yaml;
+
+use super::parse::File;
+use super::proc::Proc;
+use share_structures::State;
+
+/// max_rss / max_vms thresholds of a program, checked every `interval`.
+#[derive(Debug, Clone)]
+pub struct MemoryWatch {
+    pub max_rss: Option<u64>,
+    pub max_vms: Option<u64>,
+    pub grace: Duration,
+    pub interval: Duration,
+    pub last_check: Option<Instant>,
+    pub over_since: Option<Instant>,
+}
+
+impl MemoryWatch {
+    pub fn from_file(parse_file: &File) -> Option<Self> {
+        if parse_file.max_rss.is_none() && parse_file.max_vms.is_none() {
+            return None;
+        }
+        Some(MemoryWatch {
+            max_rss: parse_file.max_rss,
+            max_vms: parse_file.max_vms,
+            grace: Duration::from_secs(
+                parse_file
+                    .memory_grace
+                    .unwrap_or(30)
+                    .try_into()
+                    .unwrap_or(0),
+            ),
+            interval: Duration::from_secs(
+                parse_file
+                    .memory_interval
+                    .unwrap_or(5)
+                    .try_into()
+                    .unwrap_or(1),
+            ),
+            last_check: None,
+            over_since: None,
+        })
+    }
+}
+
+// bytes: `1048576`, `512K`, `64M`, `2G`
+pub fn parse_size(yaml: &Yaml) -> Option<u64> {
+    if let Some(size) = yaml.as_i64() {
+        return Some(size as u64);
+    }
+    let size = yaml.as_str()?.trim();
+    let (number, unit) =
+        size.split_at(size.find(|c: char| c.is_alphabetic()).unwrap_or(size.len()));
+    let number: u64 = number.trim().parse().ok()?;
+
+    match unit.to_uppercase().as_str() {
+        "" | "B" => Some(number),
+        "K" | "KB" => Some(number << 10),
+        "M" | "MB" => Some(number << 20),
+        "G" | "GB" => Some(number << 30),
+        _ => None,
+    }
+}
+
+pub fn size_to_string(size: u64) -> String {
+    match size {
+        s if s >= 1 << 30 => format!("{:.1}G", s as f64 / (1u64 << 30) as f64),
+        s if s >= 1 << 20 => format!("{:.1}M", s as f64 / (1u64 << 20) as f64),
+        s => format!("{}K", s >> 10),
+    }
+}
+
+fn parent_pid(pid: i32) -> Option<i32> {
+    let stat = fs::read_to_string(format!("/proc/{}/stat", pid)).ok()?;
+    // the command name can contain spaces, fields restart after the last ')'
+    let fields: Vec<&str> = stat[stat.rfind(')')? + 2..].split(' ').collect();
+
+    fields.get(1)?.parse().ok()
+}
+
+/// `pid` and all of its descendants.
+pub fn process_tree(pid: i32) -> Vec<i32> {
+    let all: Vec<(i32, i32)> = fs::read_dir("/proc")
+        .map(|dir| {
+            dir.filter_map(|entry| entry.ok()?.file_name().to_str()?.parse().ok())
+                .filter_map(|p| Some((p, parent_pid(p)?)))
+                .collect()
+        })
+        .unwrap_or_default();
+    let mut tree = vec![pid];
+    let mut i = 0;
+
+    while i < tree.len() {
+        let parent = tree[i];
+        all.iter()
+            .filter(|(_, ppid)| *ppid == parent)
+            .for_each(|(p, _)| tree.push(*p));
+        i += 1;
+    }
+    tree
+}
+
+// (VmRSS, VmSize) in bytes, summed over `pids`
+pub fn memory_usage(pids: &[i32]) -> (u64, u64) {
+    let mut usage = (0, 0);
+
+    for pid in pids {
+        let status = fs::read_to_string(format!("/proc/{}/status", pid)).unwrap_or_default();
+        for line in status.lines() {
+            let kb = |line: &str| -> u64 {
+                line.split_whitespace()
+                    .nth(1)
+                    .and_then(|v| v.parse().ok())
+                    .unwrap_or(0)
+                    << 10
+            };
+            if line.starts_with("VmRSS:") {
+                usage.0 += kb(line);
+            } else if line.starts_with("VmSize:") {
+                usage.1 += kb(line);
+            }
+        }
+    }
+    usage
+}
+
+impl Proc {
+    pub fn process_tree(&self) -> Vec<i32> {
+        match (&self.cgroup, &self.child) {
+            (Some(cgroup), _) => cgroup.pids(),
+            (None, Some(child)) => process_tree(child.id() as i32),
+            (None, None) => vec![],
+        }
+    }
+
+    /// Restart a RUNNING process that stays above max_rss / max_vms for the grace period.
+    pub fn check_memory(&mut self) {
+        if self.state != State::RUNNING {
+            return;
+        }
+        let mut watch = match self.memory_watch.take() {
+            Some(watch) => watch,
+            None => return,
+        };
+
+        let due = match watch.last_check {
+            Some(last) => last.elapsed() >= watch.interval,
+            None => true,
+        };
+        if due {
+            let (rss, vms) = memory_usage(&self.process_tree());
+            let over = match (watch.max_rss, watch.max_vms) {
+                (Some(max), _) if rss > max => Some(("rss", rss, max)),
+                (_, Some(max)) if vms > max => Some(("vms", vms, max)),
+                _ => None,
+            };
+
+            watch.last_check = Some(Instant::now());
+            match over {
+                Some((kind, value, max)) => {
+                    let since = *watch.over_since.get_or_insert(Instant::now());
+                    if since.elapsed() >= watch.grace {
+                        println!(
+                            "{} WARN memory: {} {} {} above max_{} {} for {} seconds, restarting",
+                            Local::now().format("%d/%m/%Y %H:%M:%S"),
+                            self.get_name(),
+                            kind,
+                            size_to_string(value),
+                            kind,
+                            size_to_string(max),
+                            since.elapsed().as_secs()
+                        );
+                        watch.over_since = None;
+                        self.restart_on_stop = true;
+                        let _ = self.stop();
+                    }
+                }
+                None => watch.over_since = None,
+            }
+        }
+        self.memory_watch = Some(watch);
+    }
+}
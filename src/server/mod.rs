@@ -1,19 +1,50 @@
 use bincode::{deserialize, serialize};
 use std::env;
+use std::ffi::OsString;
 use std::io::{Read, Write};
 use std::net::{Shutdown, TcpListener, TcpStream};
 use std::sync::{Arc, Mutex};
 use std::thread::{self, spawn, JoinHandle};
 use std::time::Duration;
 
+pub mod app_reload;
+pub mod cgroup;
+pub mod condition;
 pub mod conf;
+pub mod cron;
+pub mod deferred;
+pub mod details;
+pub mod exit;
 // pub mod quit;
 pub mod file;
+pub mod isolate;
+pub mod job;
+pub mod landlock;
+pub mod limits;
 pub mod log;
 pub mod loop_exec;
+pub mod memory;
+pub mod notify;
 pub mod parse;
+pub mod privilege;
 pub mod proc;
+pub mod queue;
+pub mod relation;
+pub mod replace;
+pub mod rolling;
+pub mod runtime;
+pub mod rusage;
+pub mod scale;
+pub mod sched;
+pub mod seccomp;
+pub mod shutdown;
+pub mod socket;
+pub mod spawn;
+pub mod syscalls;
 pub mod task;
+pub mod tmp;
+pub mod uptime;
+pub mod watch;
 mod tool;
 
 use conf::Taskmaster;
@@ -60,12 +91,67 @@ fn handle_client(mut stream: TcpStream, task: Arc<Mutex<Taskmaster>>) {
                     Status => {
                         res = task.lock().unwrap().status();
                     }
+                    Details(proc_name) => {
+                        res = task.lock().unwrap().details(proc_name);
+                    }
                     Reload => {
                         res = task.lock().unwrap().reload();
                     }
+                    ReloadApp(program) => {
+                        // the lock is released while a reload_command runs and for reload_verify
+                        let reloaded = app_reload::reload_app(&task, program.clone());
+                        res = match reloaded.success.is_ok() {
+                            true => app_reload::verify_reload(&task, program, reloaded),
+                            false => reloaded,
+                        };
+                    }
+                    Run(job, wait) => {
+                        // the lock is released while waiting for the job
+                        let started = task.lock().unwrap().run(job.clone(), wait);
+                        res = match wait && started.success.is_ok() {
+                            true => job::wait_job(&task, job),
+                            false => started,
+                        };
+                    }
+                    Replace(proc_name) => {
+                        let started = task.lock().unwrap().replace(proc_name.clone());
+                        res = match started.success.is_ok() {
+                            true => replace::wait_replace(&task, proc_name),
+                            false => started,
+                        };
+                    }
+                    Deferred(cmd, at, line) => {
+                        res = task.lock().unwrap().defer(*cmd, at, line);
+                    }
+                    Pending => {
+                        res = task.lock().unwrap().pending();
+                    }
+                    Cancel(id) => {
+                        res = task.lock().unwrap().cancel(id);
+                    }
+                    Scale(program, numprocs, save) => {
+                        res = task.lock().unwrap().scale(program, numprocs, save);
+                    }
+                    Progress => {
+                        res = Response {
+                            cmd: Progress,
+                            success: Err("not a command".to_string()),
+                            content: vec![],
+                        };
+                    }
+                    RollingRestart(program, batch) => {
+                        let started = task.lock().unwrap().rolling_restart(program.clone(), batch);
+                        res = match started.success.is_ok() {
+                            true => rolling::wait_rolling(&task, program, batch, &mut stream),
+                            false => started,
+                        };
+                    }
                 }
                 let res: Vec<u8> = serialize(&res).unwrap();
-                stream.write(res.as_slice()).unwrap();
+                // the client may be gone, after a long wait
+                if let Err(e) = stream.write_all(res.as_slice()) {
+                    println!("can't answer the client: {e}");
+                }
             }
             true
         }
@@ -125,26 +211,42 @@ fn drain_proc(process: &mut Vec<Proc>, index_lst: &mut Vec<usize>, numprocs: Opt
 fn process_handler(taskmaster: Arc<Mutex<Taskmaster>>) {
     let mut app = taskmaster.lock().unwrap();
     let mut to_rm_queue = vec![];
+    let shutting_down = app.shutdown_code.is_some();
 
     for task in &mut app.task_lst {
         for (p_index, proc) in &mut task.process_lst.iter_mut().enumerate() {
             match &mut proc.child {
-                Some(child) => {
-                    match child.try_wait() {
+                Some(_) => {
+                    match proc.try_reap() {
                         Ok(Some(exit_status)) => {
                             // Process has exited
+                            proc.reset_start_retries();
                             if proc.state == State::STOPPING {
                                 proc.change_to_stopped();
                             } else {
                                 proc.change_to_exited();
                             }
                             proc.exit_error = exit_status.code();
-                            proc.test_autorestart(task.parse_file.clone(), exit_status);
+                            let oom_killed = proc.reap_cgroup();
+                            proc.remove_private_tmp();
+                            proc.record_exit(exit_status, oom_killed);
+                            proc.finish_job(exit_status);
+                            if proc.stale_sockets {
+                                proc.setup_again(&task.parse_file, &task.sockets);
+                            }
+                            if !shutting_down {
+                                proc.test_autorestart(task.parse_file.clone(), exit_status);
+                            }
+                            if std::mem::take(&mut proc.restart_on_stop)
+                                && proc.state == State::STOPPED
+                            {
+                                let _ = proc.start();
+                            }
                         }
                         Ok(None) => {
                             // Process is running
                             if proc.state == State::STARTING {
-                                proc.change_to_running();
+                                proc.check_starting();
                             } else if proc.state == State::STOPPING {
                                 let duration = proc.started_time.unwrap().elapsed();
                                 let exp_duration = Duration::from_secs(
@@ -152,20 +254,32 @@ fn process_handler(taskmaster: Arc<Mutex<Taskmaster>>) {
                                 );
                                 if duration > exp_duration {
                                     // force kill
-                                    child.kill().expect("proc wasn't running");
+                                    if let Some(child) = &mut proc.child {
+                                        child.kill().expect("proc wasn't running");
+                                    }
+                                    proc.kill_cgroup();
                                 }
                                 proc.change_state(State::STOPPING);
                             }
+                            proc.check_watchdog();
+                            proc.check_memory();
+                            proc.check_timeout();
                         }
                         Err(e) => println!("error attempting to wait: {e}"),
                     }
                 }
                 None => {
-                    if proc.name == None {
+                    if proc.name == None || proc.retiring {
                         to_rm_queue.push(p_index);
+                    } else if proc.state == State::WAITING {
+                        let _ = proc.start();
                     }
                 }
             }
+            if !shutting_down {
+                proc.check_schedule();
+                proc.check_file_watch();
+            }
         }
         // remove proc
         drain_proc(
@@ -176,23 +290,45 @@ fn process_handler(taskmaster: Arc<Mutex<Taskmaster>>) {
     }
     // remove empty Task
     app.task_lst.retain(|task| !task.is_active == false);
+    // a failed replacement is dropped before it counts as FATAL
+    app.step_replacements();
+    app.check_critical();
+    if app.shutdown_code.is_none() {
+        app.enforce_relations();
+        app.step_rolling_restarts();
+        app.recycle_processes();
+        app.run_deferred();
+        app.dispatch_queued();
+    }
 }
 
 fn supervisor(taskmaster: Arc<Mutex<Taskmaster>>) {
     // Surveillance of process
     spawn(move || loop {
         process_handler(taskmaster.clone());
+        let mut app = taskmaster.lock().unwrap();
+        if let Some(code) = app.shutdown_step() {
+            app.teardown();
+            println!("all processes stopped, exiting with code {}", code);
+            std::process::exit(code);
+        }
+        drop(app);
         thread::sleep(Duration::from_secs(2));
     });
 }
 
 fn main() {
+    let args: Vec<OsString> = env::args_os().collect();
+    if args.get(1).map(|arg| arg == socket::LISTEN_EXEC_ARG) == Some(true) {
+        socket::listen_exec(&args[2..]);
+    }
     let path = env::args()
         .nth(1)
         .expect("\nUsage :\n\tcargo run --bin server -- CONFIG_FILE\n\n");
 
     let mut app = Taskmaster::new(&path);
     app.set_all_command();
+    app.load_deferred();
     let app = Arc::new(Mutex::new(app));
     let t = spawn_thread_up_ex(app.clone());
     let _ = t.join().unwrap();
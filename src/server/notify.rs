@@ -0,0 +1,167 @@
+use nix::cmsg_space;
+use nix::errno::Errno;
+use nix::sys::socket::{
+    recvmsg, setsockopt, sockopt, ControlMessageOwned, MsgFlags, UnixAddr, UnixCredentials,
+};
+use nix::unistd::{chown, Gid, Uid};
+use std::fs;
+use std::io::{self, IoSliceMut};
+use std::os::unix::fs::PermissionsExt;
+use std::os::unix::io::AsRawFd;
+use std::os::unix::net::UnixDatagram;
+use std::time::{Duration, Instant};
+
+use super::proc::Proc;
+use super::runtime::private_dir;
+use share_structures::State;
+
+/// Datagram socket handed to the child through NOTIFY_SOCKET (sd_notify protocol).
+#[derive(Debug)]
+pub struct NotifySocket {
+    pub path: String,
+    socket: UnixDatagram,
+}
+
+impl NotifySocket {
+    /// In the daemon's runtime dir, writable by the program only. With
+    /// `user:`, the socket is handed to that user: the child could not send
+    /// to a socket of root's otherwise.
+    pub fn bind(proc_name: &str, owner: Option<(Uid, Gid)>) -> io::Result<Self> {
+        let dir = private_dir("notify").map_err(io::Error::other)?;
+        let path = dir
+            .join(format!("{}.notify", proc_name))
+            .to_string_lossy()
+            .to_string();
+        let _ = fs::remove_file(&path);
+        let socket = UnixDatagram::bind(&path)?;
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o600))?;
+        if let Some((uid, gid)) = owner {
+            chown(path.as_str(), Some(uid), Some(gid))?;
+        }
+        // the kernel attaches the sender's pid to each datagram
+        setsockopt(socket.as_raw_fd(), sockopt::PassCred, &true)?;
+
+        socket.set_nonblocking(true)?;
+        Ok(NotifySocket { path, socket })
+    }
+
+    // drain every pending datagram, one "KEY=VALUE" per line, with the sender pid
+    pub fn messages(&self) -> Vec<(i32, String)> {
+        let mut buf = [0u8; 4096];
+        let mut messages = vec![];
+
+        loop {
+            let mut iov = [IoSliceMut::new(&mut buf)];
+            let mut cmsg = cmsg_space!(UnixCredentials);
+            let received = recvmsg::<UnixAddr>(
+                self.socket.as_raw_fd(),
+                &mut iov,
+                Some(&mut cmsg),
+                MsgFlags::MSG_DONTWAIT,
+            )
+            .map(|msg| {
+                let pid = msg.cmsgs().find_map(|cmsg| match cmsg {
+                    ControlMessageOwned::ScmCredentials(cred) => Some(cred.pid()),
+                    _ => None,
+                });
+                (msg.bytes, pid)
+            });
+
+            match received {
+                Ok((size, Some(pid))) => String::from_utf8_lossy(&buf[..size])
+                    .lines()
+                    .filter(|line| !line.is_empty())
+                    .for_each(|line| messages.push((pid, line.to_string()))),
+                Ok((_, None)) => continue,
+                Err(Errno::EAGAIN) => break,
+                Err(e) => {
+                    println!("error reading notify socket {}: {e}", self.path);
+                    break;
+                }
+            }
+        }
+        messages
+    }
+}
+
+// how far up from a sender its process is looked for
+const MAX_DEPTH: usize = 32;
+
+// its parent pid, from /proc/<pid>/stat: "pid (comm) state ppid ..."
+fn parent_pid(pid: i32) -> Option<i32> {
+    let stat = fs::read_to_string(format!("/proc/{}/stat", pid)).ok()?;
+    let after_comm = &stat[stat.rfind(')')? + 1..];
+
+    after_comm.split_whitespace().nth(1)?.parse().ok()
+}
+
+impl Drop for NotifySocket {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+impl Proc {
+    pub fn read_notify(&mut self) {
+        let messages = match &self.notify {
+            Some(socket) => socket.messages(),
+            None => return,
+        };
+
+        for (pid, msg) in messages {
+            if !self.is_own_pid(pid) {
+                println!(
+                    "{}: ignoring notify message from pid {}, not one of its processes",
+                    self.get_name(),
+                    pid
+                );
+                continue;
+            }
+            if msg == "WATCHDOG=1" {
+                self.last_watchdog = Some(Instant::now());
+            }
+        }
+    }
+
+    // the child, one of its descendants, or a process of its cgroup
+    fn is_own_pid(&self, pid: i32) -> bool {
+        let child = match self.pid {
+            Some(child) => child as i32,
+            None => return false,
+        };
+        let in_cgroup = self
+            .cgroup
+            .as_ref()
+            .map(|cgroup| cgroup.pids().contains(&pid))
+            == Some(true);
+        let mut ancestor = Some(pid);
+
+        for _ in 0..MAX_DEPTH {
+            match ancestor {
+                Some(ancestor) if ancestor == child => return true,
+                Some(ancestor) if ancestor > 1 => {}
+                _ => break,
+            }
+            ancestor = ancestor.and_then(parent_pid);
+        }
+        in_cgroup
+    }
+
+    /// Restart a RUNNING process whose keepalives stopped for longer than watchdog_sec.
+    pub fn check_watchdog(&mut self) {
+        self.read_notify();
+        if self.state != State::RUNNING {
+            return;
+        }
+        if let Some(timeout) = self.watchdog {
+            let since = self.last_watchdog.or(self.started_time);
+            let elapsed = since.map(|t| t.elapsed()).unwrap_or(Duration::ZERO);
+
+            if elapsed > timeout {
+                println!("{}", self.watchdog_log(elapsed));
+                self.restart_on_stop = true;
+                let _ = self.stop();
+            }
+        }
+    }
+}
@@ -1,7 +1,23 @@
 use nix::sys::{signal::Signal, stat::Mode};
 use yaml_rust::Yaml;
 
-use super::{file::FileLog, loop_exec::Autorestart, tool::parse_to_string};
+use super::{
+    cgroup::CgroupLimits,
+    condition::{parse_start_conditions, StartCondition},
+    cron::Schedule,
+    file::FileLog,
+    isolate::Isolate,
+    landlock::Landlock,
+    limits::{parse_rlimits, Rlimit},
+    loop_exec::Autorestart,
+    memory::parse_size,
+    privilege::parse_capabilities,
+    relation::parse_name_list,
+    sched::Sched,
+    seccomp::Seccomp,
+    socket::{parse_sockets, SocketSpec},
+    tool::parse_to_string,
+};
 
 #[derive(Debug, Clone)]
 pub struct File {
@@ -21,6 +37,41 @@ pub struct File {
     pub bool_stdout: bool,
     pub bool_stderr: bool,
     pub file_log: Option<FileLog>,
+    pub watchdog_sec: Option<i64>,
+    pub start_conditions: Vec<StartCondition>,
+    pub start_stagger: Option<i64>,
+    pub start_splay: Option<i64>,
+    pub fallback: Option<String>,
+    pub conflicts: Vec<String>,
+    pub critical: bool,
+    pub rlimits: Vec<Rlimit>,
+    pub cgroup_parent: Option<String>,
+    pub cgroup_limits: CgroupLimits,
+    pub max_rss: Option<u64>,
+    pub max_vms: Option<u64>,
+    pub memory_grace: Option<i64>,
+    pub memory_interval: Option<i64>,
+    pub sched: Sched,
+    pub user: Option<String>,
+    pub group: Option<String>,
+    pub capabilities: Option<Vec<u32>>,
+    pub no_new_privs: bool,
+    pub seccomp: Option<Seccomp>,
+    pub landlock: Option<Landlock>,
+    pub isolate: Option<Isolate>,
+    pub private_tmp: bool,
+    pub schedule: Option<Schedule>,
+    pub oneshot: bool,
+    pub timeout: Option<i64>,
+    pub watch: Vec<String>,
+    pub watch_debounce: Option<i64>,
+    pub watch_signal: Option<Signal>,
+    pub max_uptime: Option<i64>,
+    pub max_uptime_jitter: Option<i64>,
+    pub sockets: Vec<SocketSpec>,
+    pub reload_signal: Option<Signal>,
+    pub reload_command: Option<String>,
+    pub reload_verify: Option<i64>,
 }
 
 impl File {
@@ -53,6 +104,44 @@ impl File {
             bool_stdout: yaml_file["stdout"].is_badvalue(),
             bool_stderr: yaml_file["stderr"].is_badvalue(),
             file_log: None,
+            watchdog_sec: yaml_file["watchdog_sec"].as_i64(),
+            start_conditions: parse_start_conditions(&yaml_file["start_conditions"]),
+            start_stagger: yaml_file["start_stagger"].as_i64(),
+            start_splay: yaml_file["start_splay"].as_i64(),
+            fallback: parse_to_string(yaml_file["fallback"].as_str()),
+            conflicts: parse_name_list(&yaml_file["conflicts"]),
+            critical: yaml_file["critical"].as_bool().unwrap_or(false)
+                || yaml_file["on_fatal"].as_str() == Some("shutdown"),
+            rlimits: parse_rlimits(&yaml_file["rlimits"]),
+            cgroup_parent: parse_to_string(yaml_file["cgroup_parent"].as_str()),
+            cgroup_limits: CgroupLimits::from_yaml(yaml_file),
+            max_rss: parse_size(&yaml_file["max_rss"]),
+            max_vms: parse_size(&yaml_file["max_vms"]),
+            memory_grace: yaml_file["memory_grace"].as_i64(),
+            memory_interval: yaml_file["memory_interval"].as_i64(),
+            sched: Sched::from_yaml(yaml_file),
+            user: parse_to_string(yaml_file["user"].as_str())
+                .or(yaml_file["user"].as_i64().map(|uid| uid.to_string())),
+            group: parse_to_string(yaml_file["group"].as_str())
+                .or(yaml_file["group"].as_i64().map(|gid| gid.to_string())),
+            capabilities: parse_capabilities(&yaml_file["capabilities"]),
+            no_new_privs: yaml_file["no_new_privs"].as_bool().unwrap_or(false),
+            seccomp: Seccomp::from_yaml(&yaml_file["seccomp"]),
+            landlock: Landlock::from_yaml(yaml_file),
+            isolate: Isolate::from_yaml(yaml_file),
+            private_tmp: yaml_file["private_tmp"].as_bool().unwrap_or(false),
+            schedule: Schedule::from_yaml(yaml_file),
+            oneshot: parse_oneshot(yaml_file["type"].as_str()),
+            timeout: yaml_file["timeout"].as_i64(),
+            watch: parse_name_list(&yaml_file["watch"]),
+            watch_debounce: yaml_file["watch_debounce"].as_i64(),
+            watch_signal: parse_signal_key(yaml_file, "watch_signal"),
+            max_uptime: yaml_file["max_uptime"].as_i64(),
+            max_uptime_jitter: yaml_file["max_uptime_jitter"].as_i64(),
+            sockets: parse_sockets(&yaml_file["sockets"]),
+            reload_signal: parse_signal_key(yaml_file, "reload_signal"),
+            reload_command: parse_to_string(yaml_file["reload_command"].as_str()),
+            reload_verify: yaml_file["reload_verify"].as_i64(),
         }
     }
 
@@ -101,7 +190,8 @@ impl File {
 
     pub fn set_autostart_default(&mut self) {
         if self.autostart == None {
-            self.autostart = Some(true);
+            // scheduled programs wait for their first tick
+            self.autostart = Some(self.schedule.is_none());
         }
     }
 
@@ -112,7 +202,9 @@ impl File {
     }
 
     pub fn set_exitcode_default(&mut self) {
-        self.exitcodes = vec![0];
+        if self.exitcodes.is_empty() {
+            self.exitcodes = vec![0];
+        }
     }
 
     pub fn set_start_retries_default(&mut self) {
@@ -167,16 +259,44 @@ pub fn parse_autorestart(
     y
 }
 
+// `type: oneshot` runs to completion, anything else is a long-running program
+pub fn parse_oneshot(program_type: Option<&str>) -> bool {
+    match program_type {
+        Some("oneshot") => true,
+        Some("simple") | None => false,
+        Some(other) => {
+            println!("type: ignoring unknown type {}", other);
+            false
+        }
+    }
+}
+
 pub fn parse_signal(sig: Option<&str>) -> Signal {
-    match sig {
-        Some(signal) => match signal {
-            "TERM" => Signal::SIGTERM,
-            "HUP" => Signal::SIGHUP,
-            "INT" => Signal::SIGINT,
-            "QUIT" => Signal::SIGQUIT,
-            "KILL" => Signal::SIGKILL,
-            _ => Signal::SIGTERM,
-        },
-        None => Signal::SIGTERM,
+    sig.and_then(signal_from_name).unwrap_or(Signal::SIGTERM)
+}
+
+fn signal_from_name(signal: &str) -> Option<Signal> {
+    match signal {
+        "TERM" => Some(Signal::SIGTERM),
+        "HUP" => Some(Signal::SIGHUP),
+        "INT" => Some(Signal::SIGINT),
+        "QUIT" => Some(Signal::SIGQUIT),
+        "KILL" => Some(Signal::SIGKILL),
+        "USR1" => Some(Signal::SIGUSR1),
+        "USR2" => Some(Signal::SIGUSR2),
+        _ => None,
+    }
+}
+
+// an optional signal key, a name we don't know is dropped rather than read as TERM
+fn parse_signal_key(yaml_file: &Yaml, key: &str) -> Option<Signal> {
+    let name = yaml_file[key].as_str()?;
+    let signal = signal_from_name(name);
+    if signal.is_none() {
+        println!(
+            "{}: ignoring unknown signal {} (expected TERM, HUP, INT, QUIT, KILL, USR1 or USR2)",
+            key, name
+        );
     }
+    signal
 }
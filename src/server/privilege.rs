@@ -0,0 +1,257 @@
+use nix::libc;
+use nix::unistd::{getgrouplist, setgid, setgroups, setuid, Gid, Group, Uid, User};
+use std::ffi::CString;
+use std::fs;
+use std::io;
+use std::os::unix::process::CommandExt;
+use yaml_rust::Yaml;
+
+use super::parse::File;
+use super::proc::Proc;
+
+const CAP_NAMES: [&str; 41] = [
+    "CHOWN",
+    "DAC_OVERRIDE",
+    "DAC_READ_SEARCH",
+    "FOWNER",
+    "FSETID",
+    "KILL",
+    "SETGID",
+    "SETUID",
+    "SETPCAP",
+    "LINUX_IMMUTABLE",
+    "NET_BIND_SERVICE",
+    "NET_BROADCAST",
+    "NET_ADMIN",
+    "NET_RAW",
+    "IPC_LOCK",
+    "IPC_OWNER",
+    "SYS_MODULE",
+    "SYS_RAWIO",
+    "SYS_CHROOT",
+    "SYS_PTRACE",
+    "SYS_PACCT",
+    "SYS_ADMIN",
+    "SYS_BOOT",
+    "SYS_NICE",
+    "SYS_RESOURCE",
+    "SYS_TIME",
+    "SYS_TTY_CONFIG",
+    "MKNOD",
+    "LEASE",
+    "AUDIT_WRITE",
+    "AUDIT_CONTROL",
+    "SETFCAP",
+    "MAC_OVERRIDE",
+    "MAC_ADMIN",
+    "SYSLOG",
+    "WAKE_ALARM",
+    "BLOCK_SUSPEND",
+    "AUDIT_READ",
+    "PERFMON",
+    "BPF",
+    "CHECKPOINT_RESTORE",
+];
+
+const LINUX_CAPABILITY_VERSION_3: u32 = 0x20080522;
+
+#[repr(C)]
+struct CapHeader {
+    version: u32,
+    pid: libc::c_int,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+struct CapData {
+    effective: u32,
+    permitted: u32,
+    inheritable: u32,
+}
+
+/// `CAP_NET_BIND_SERVICE`, `net_bind_service`...
+fn cap_from_name(name: &str) -> Option<u32> {
+    let name = name.to_uppercase();
+    let name = name.strip_prefix("CAP_").unwrap_or(&name);
+
+    CAP_NAMES
+        .iter()
+        .position(|cap| *cap == name)
+        .map(|cap| cap as u32)
+}
+
+pub fn parse_capabilities(yaml: &Yaml) -> Option<Vec<u32>> {
+    let names = yaml.as_vec()?;
+    let mut caps = vec![];
+
+    for name in names {
+        match name.as_str().and_then(cap_from_name) {
+            Some(cap) => caps.push(cap),
+            None => println!("capabilities: ignoring unknown capability {:?}", name),
+        }
+    }
+    Some(caps)
+}
+
+// highest capability the running kernel knows about
+fn last_cap() -> u32 {
+    fs::read_to_string("/proc/sys/kernel/cap_last_cap")
+        .ok()
+        .and_then(|last| last.trim().parse().ok())
+        .unwrap_or(CAP_NAMES.len() as u32 - 1)
+}
+
+#[derive(Debug, Clone)]
+struct UserSwitch {
+    name: String,
+    uid: Uid,
+    gid: Gid,
+    groups: Vec<Gid>,
+    home: String,
+}
+
+// `user` is a name or a uid, `group` overrides the primary group of the user
+fn find_user(user: &str, group: Option<&str>) -> Result<UserSwitch, String> {
+    let found = match user.parse::<u32>() {
+        Ok(uid) => User::from_uid(Uid::from_raw(uid)),
+        Err(_) => User::from_name(user),
+    };
+    let user = match found {
+        Ok(Some(user)) => user,
+        _ => return Err(format!("find user {}", user)),
+    };
+    let gid = match group {
+        Some(group) => match group.parse::<u32>() {
+            Ok(gid) => Gid::from_raw(gid),
+            Err(_) => match Group::from_name(group) {
+                Ok(Some(group)) => group.gid,
+                _ => return Err(format!("find group {}", group)),
+            },
+        },
+        None => user.gid,
+    };
+    let groups = CString::new(user.name.as_bytes())
+        .ok()
+        .and_then(|name| getgrouplist(&name, gid).ok())
+        .unwrap_or(vec![gid]);
+
+    Ok(UserSwitch {
+        name: user.name,
+        uid: user.uid,
+        gid,
+        groups,
+        home: user.dir.to_string_lossy().to_string(),
+    })
+}
+
+/// uid and gid the process of `parse_file` runs as, None when it keeps ours.
+pub fn user_owner(parse_file: &File) -> Option<(Uid, Gid)> {
+    let user = find_user(parse_file.user.as_ref()?, parse_file.group.as_deref()).ok()?;
+
+    Some((user.uid, user.gid))
+}
+
+fn capset(caps: &[u32]) -> io::Result<()> {
+    let mut header = CapHeader {
+        version: LINUX_CAPABILITY_VERSION_3,
+        pid: 0,
+    };
+    let mut data = [CapData::default(); 2];
+
+    for cap in caps {
+        let bit = 1 << (cap % 32);
+        let set = &mut data[(cap / 32) as usize];
+        set.effective |= bit;
+        set.permitted |= bit;
+        set.inheritable |= bit;
+    }
+    match unsafe { libc::syscall(libc::SYS_capset, &mut header, data.as_mut_ptr()) } {
+        0 => Ok(()),
+        _ => Err(io::Error::last_os_error()),
+    }
+}
+
+fn prctl(option: libc::c_int, arg2: libc::c_ulong, arg3: libc::c_ulong) -> io::Result<()> {
+    match unsafe { libc::prctl(option, arg2, arg3, 0, 0) } {
+        0 => Ok(()),
+        _ => Err(io::Error::last_os_error()),
+    }
+}
+
+impl Proc {
+    /// `user`, `capabilities` and `no_new_privs`, run after the hooks that still need root.
+    pub fn setup_command_privileges(&mut self, parse_file: &File) {
+        let user = parse_file
+            .user
+            .as_ref()
+            .map(|user| find_user(user, parse_file.group.as_deref()));
+        let caps = parse_file.capabilities.clone();
+        let no_new_privs = parse_file.no_new_privs;
+        let last_cap = last_cap();
+        let step = self.spawn_step.clone();
+
+        if user.is_none() && caps.is_none() && !no_new_privs {
+            return;
+        }
+        let labels = (
+            format!("drop capabilities to {:?}", parse_file.capabilities),
+            match &user {
+                Some(Ok(user)) => format!("switch to user {}", user.name),
+                Some(Err(step)) => step.clone(),
+                None => String::new(),
+            },
+            "set no_new_privs".to_string(),
+        );
+
+        if let Some(command) = &mut self.command {
+            if let Some(Ok(user)) = &user {
+                command
+                    .env("USER", &user.name)
+                    .env("LOGNAME", &user.name)
+                    .env("HOME", &user.home);
+            }
+            unsafe {
+                command.pre_exec(move || {
+                    if let Some(caps) = &caps {
+                        step.set(&labels.0);
+                        for cap in (0..=last_cap).filter(|cap| !caps.contains(cap)) {
+                            prctl(libc::PR_CAPBSET_DROP, cap as libc::c_ulong, 0)?;
+                        }
+                    }
+                    match &user {
+                        Some(Ok(user)) => {
+                            step.set(&labels.1);
+                            // keep the permitted set across setuid, capset below trims it
+                            prctl(libc::PR_SET_KEEPCAPS, 1, 0)?;
+                            setgroups(&user.groups)?;
+                            setgid(user.gid)?;
+                            setuid(user.uid)?;
+                        }
+                        Some(Err(_)) => {
+                            step.set(&labels.1);
+                            return Err(io::Error::from_raw_os_error(libc::ENOENT));
+                        }
+                        None => {}
+                    }
+                    if let Some(caps) = &caps {
+                        step.set(&labels.0);
+                        capset(caps)?;
+                        // ambient capabilities survive the exec of a non-root program
+                        for cap in caps {
+                            prctl(
+                                libc::PR_CAP_AMBIENT,
+                                libc::PR_CAP_AMBIENT_RAISE as libc::c_ulong,
+                                *cap as libc::c_ulong,
+                            )?;
+                        }
+                    }
+                    if no_new_privs {
+                        step.set(&labels.2);
+                        prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0)?;
+                    }
+                    Ok(())
+                });
+            }
+        }
+    }
+}
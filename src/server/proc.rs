@@ -1,7 +1,10 @@
 use chrono::{DateTime, Local};
 use nix::sys::{signal::Signal, stat};
 use nix::unistd::Pid;
+use std::collections::VecDeque;
 use std::os::unix::process::ExitStatusExt;
+use std::sync::mpsc::Receiver;
+use std::sync::Arc;
 use std::thread;
 use std::time::Duration;
 use std::{
@@ -14,10 +17,27 @@ use std::{
 use crate::loop_exec::Autorestart;
 
 use super::{
+    cgroup::Cgroup,
+    condition::StartCondition,
+    cron::Schedule,
+    exit::ExitRecord,
     file::FileLog,
-    loop_exec::{always_true, test_autorestart, test_autostart, test_time_starting, LoopRestart},
+    landlock::LandlockRules,
+    loop_exec::{
+        always_true, test_autorestart, test_autorestart_nb, test_autostart, test_time_starting,
+        LoopRestart,
+    },
+    memory::MemoryWatch,
+    notify::NotifySocket,
     parse::File,
+    privilege::user_owner,
+    rusage::Usage,
+    socket::ListenExec,
+    spawn::SpawnStep,
+    tmp::PrivateTmp,
     tool::DurationDate,
+    uptime::MaxUptime,
+    watch::FileWatch,
 };
 use share_structures::State;
 
@@ -39,9 +59,47 @@ pub struct Proc {
     pub loop_test_file_config: Option<fn(&File, &Proc) -> bool>,
     pub loop_bool: bool,
     pub stopsignal: Signal,
-    
+
     pub exp_exit: Vec<i32>,
     pub exp_duration: Duration,
+    pub notify: Option<NotifySocket>,
+    pub watchdog: Option<Duration>,
+    pub last_watchdog: Option<Instant>,
+    pub restart_on_stop: bool,
+    pub start_conditions: Vec<StartCondition>,
+    // the start conditions check in flight
+    pub condition_check: Option<Receiver<Result<(), String>>>,
+    pub instance: i64,
+    pub start_stagger: Duration,
+    pub start_splay: Duration,
+    pub queued_until: Option<Instant>,
+    pub spawn_step: Arc<SpawnStep>,
+    pub cgroup: Option<Cgroup>,
+    pub memory_watch: Option<MemoryWatch>,
+    pub seccomp: bool,
+    pub exit_history: VecDeque<ExitRecord>,
+    pub private_tmp: Option<PrivateTmp>,
+    pub landlock: Option<LandlockRules>,
+    // started_time is reset on RUNNING and on stop, this one is not
+    pub spawned_time: Option<Instant>,
+    // pid, status and rusage of the last child waited for
+    pub reaped: Option<(i32, ExitStatus, Usage)>,
+    pub schedule: Option<Schedule>,
+    pub oneshot: bool,
+    pub timeout: Option<Duration>,
+    // the current run was stopped by its timeout
+    pub timed_out: bool,
+    pub file_watch: Option<FileWatch>,
+    pub max_uptime: Option<MaxUptime>,
+    // uptime the current run is recycled at, max_uptime plus its jitter
+    pub uptime_limit: Option<Duration>,
+    // scaled down, removed from its program once stopped
+    pub retiring: bool,
+    // bumped by each `restart --replace`
+    pub generation: u32,
+    pub listen: Option<Arc<ListenExec>>,
+    // set up for the sockets before a reload, set up again once down
+    pub stale_sockets: bool,
 }
 
 impl fmt::Debug for Proc {
@@ -59,15 +117,36 @@ impl fmt::Debug for Proc {
 impl Proc {
     // ______________________ Actions _________________________
     pub fn start(&mut self) -> Result<String, String> {
-        if self.state == State::STOPPED || self.state == State::EXITED {
-            if let Ok(child) = self.command.as_mut().expect("Start").spawn() {
-                println!("INFO spawned: {} with {}", self.get_name(), child.id());
-                self.state = State::STARTING;
-                self.child.get_or_insert(child);
-                Ok(format!("{}: started", self.get_name()))
-            } else {
-                println!("Command didn't start");
-                Err(format!("Command didn't start"))
+        if self.retiring {
+            return Err(format!("{}: ERROR (scaled down)", self.get_name()));
+        }
+        if self.state == State::STOPPED
+            || self.state == State::EXITED
+            || self.state == State::WAITING
+            || self.state == State::QUEUED
+            || self.state == State::FATAL
+            || self.state == State::SUCCEEDED
+        {
+            if self.state == State::FATAL {
+                self.nbr_restart = 0;
+            }
+            if let Some(reason) = self.unmet_condition() {
+                let res = Ok(format!("{}: waiting ({})", self.get_name(), reason));
+                self.change_to_waiting(reason);
+                return res;
+            }
+            match self.spawn_child() {
+                Ok(child) => {
+                    println!("INFO spawned: {} with {}", self.get_name(), child.id());
+                    self.state = State::STARTING;
+                    self.child.get_or_insert(child);
+                    Ok(format!("{}: started", self.get_name()))
+                }
+                Err(e) => {
+                    let res = Err(format!("{}: ERROR (spawn error: {})", self.get_name(), e));
+                    self.spawn_failed(e);
+                    res
+                }
             }
         } else {
             Err(format!("ERROR (already started)"))
@@ -75,7 +154,12 @@ impl Proc {
     }
 
     pub fn stop(&mut self) -> Result<String, String> {
-        if self.state == State::RUNNING {
+        if self.state == State::WAITING || self.state == State::QUEUED {
+            // its result would be stale by the next start
+            self.condition_check = None;
+            self.change_to_stopped();
+            Ok(format!("{}: stopped", self.get_name()))
+        } else if self.state == State::RUNNING {
             match &mut self.child {
                 Some(child) => {
                     let pid = Pid::from_raw(child.id().try_into().unwrap());
@@ -109,8 +193,8 @@ impl Proc {
 
     pub fn exit_or_running(&mut self) -> bool {
         // rename : refresh states process and description
-        if let Some(child) = &mut self.child {
-            match child.try_wait() {
+        if self.child.is_some() {
+            match self.try_reap() {
                 Ok(Some(_)) => return true,
                 Ok(None) => return false,
                 Err(e) => {
@@ -124,18 +208,22 @@ impl Proc {
 
     pub fn exit_process(&mut self) {
         // rename : refresh states process and description
-        if let Some(child) = &mut self.child {
-            match child.try_wait() {
+        if self.child.is_some() {
+            match self.try_reap() {
                 Ok(Some(status)) => {
                     self.description = format!("exited with: {}", status);
                     self.child = None;
                     self.started_time = None;
                     self.exit_error = status.code();
                     self.change_state(State::EXITED);
+                    let oom_killed = self.reap_cgroup();
+                    self.remove_private_tmp();
+                    self.record_exit(status, oom_killed);
+                    self.finish_job(status);
                 }
                 Ok(None) => {
                     println!("status not ready yet, let's really wait");
-                    let res = child.wait();
+                    let res = self.wait_reap();
                     println!("result: {res:?}");
                 }
                 Err(e) => println!("error attempting to wait: {e}"),
@@ -143,23 +231,51 @@ impl Proc {
         }
     }
 
-    pub fn test_autorestart(&mut self, config: File, exit_status: ExitStatus) {
-        match config.autorestart {
-            Some(instruction) => match instruction {
-                Autorestart::Always => {
-                    let _ = self.restart();
-                }
-                Autorestart::OnError => {
-                    let code = exit_status.code().unwrap_or_default();
-                    let signal = exit_status.signal().unwrap_or_default();
+    /// STARTING becomes RUNNING once the process stayed up for starttime.
+    /// A rolling restart moves on at RUNNING: an instance crashing right
+    /// after its spawn must not pass for started.
+    pub fn check_starting(&mut self) {
+        let started = self.spawned_time.map(|t| t.elapsed() >= self.exp_duration);
 
-                    if !self.exp_exit.contains(&code) || self.stopsignal as i32 != signal {
-                        let _ = self.restart();
-                    }
-                }
-                Autorestart::Never => (),
+        if self.state == State::STARTING && started != Some(false) {
+            self.change_to_running();
+        }
+    }
+
+    /// On an exit, before the state changes. startretries counts the exits
+    /// before starttime in a row: an exit once RUNNING starts over, else a
+    /// long-lived program crashing once in a while ends up FATAL.
+    pub fn reset_start_retries(&mut self) {
+        if self.state == State::RUNNING {
+            self.nbr_restart = 0;
+        }
+    }
+
+    /// After an exit while supervised. A restart counts as a start retry,
+    /// after startretries of them the process gives up with FATAL, which is
+    /// what a `fallback` waits for. `unexpected` only restarts on an exit
+    /// code outside exitcodes, or a signal other than stopsignal.
+    pub fn test_autorestart(&mut self, config: File, exit_status: ExitStatus) {
+        if self.state != State::EXITED {
+            // stopped on request
+            return;
+        }
+        let restart = match config.autorestart {
+            Some(Autorestart::Always) => true,
+            Some(Autorestart::OnError) => match exit_status.code() {
+                Some(code) => !self.exp_exit.contains(&code),
+                None => exit_status.signal() != Some(self.stopsignal as i32),
             },
-            None => (),
+            Some(Autorestart::Never) | None => false,
+        };
+
+        if restart {
+            if test_autorestart_nb(&config, self) {
+                self.nbr_restart += 1;
+                let _ = self.start();
+            } else {
+                self.change_to_fatal();
+            }
         }
     }
 
@@ -178,6 +294,10 @@ impl Proc {
                 self.pid.unwrap(),
                 self.started_time.unwrap().elapsed().durationdate()
             )
+        } else if self.state == State::QUEUED {
+            self.queued_description()
+        } else if let Some(schedule) = self.schedule_description() {
+            format!("{}, {}", self.description, schedule)
         } else {
             self.description.clone()
         }
@@ -196,12 +316,9 @@ impl Proc {
     }
 
     pub fn get_exit_status(&mut self) -> Option<ExitStatus> {
-        match &mut self.child {
-            Some(child) => match child.try_wait() {
-                Ok(Some(status)) => Some(status),
-                _ => None,
-            },
-            None => None,
+        match self.try_reap() {
+            Ok(Some(status)) => Some(status),
+            _ => None,
         }
     }
 
@@ -227,6 +344,36 @@ impl Proc {
             stopsignal: Signal::SIGTERM,
             exp_exit: vec![0],
             exp_duration: Duration::new(0, 0),
+            notify: None,
+            watchdog: None,
+            last_watchdog: None,
+            restart_on_stop: false,
+            start_conditions: vec![],
+            condition_check: None,
+            instance: 0,
+            start_stagger: Duration::ZERO,
+            start_splay: Duration::ZERO,
+            queued_until: None,
+            spawn_step: Arc::new(SpawnStep::new().expect("can't map spawn step page")),
+            cgroup: None,
+            memory_watch: None,
+            seccomp: false,
+            exit_history: VecDeque::new(),
+            private_tmp: None,
+            landlock: None,
+            spawned_time: None,
+            reaped: None,
+            schedule: None,
+            oneshot: false,
+            timeout: None,
+            timed_out: false,
+            file_watch: None,
+            max_uptime: None,
+            uptime_limit: None,
+            retiring: false,
+            generation: 1,
+            listen: None,
+            stale_sockets: false,
         }
     }
 
@@ -239,6 +386,9 @@ impl Proc {
             State::BACKOFF => {}
             State::EXITED => println!("{}", self.exit_log()),
             State::FATAL => println!("{}", self.gaveup_log()),
+            State::SUCCEEDED => println!("{}", self.succeeded_log()),
+            State::WAITING => println!("{}", self.waiting_log()),
+            State::QUEUED => println!("{}", self.queued_log()),
             State::UNKNOWN => todo!(),
         }
         self.state = state;
@@ -257,6 +407,8 @@ impl Proc {
             self.pid.get_or_insert(child.id());
         };
         self.started_time = Some(Instant::now());
+        self.last_watchdog = None;
+        self.arm_max_uptime();
         // println!("{:?}", self.exp_duration);
         // thread::sleep(self.exp_duration);
         self.change_state(State::RUNNING);
@@ -280,7 +432,7 @@ impl Proc {
         match self.state {
             State::STOPPED => {
                 self.loop_test_file_config = Some(test_autostart);
-                self.loop_action_true = Some(Proc::start_loop); // Stopped -> Starting
+                self.loop_action_true = Some(Proc::enqueue); // Stopped -> Queued
                 self.loop_action_false = None;
             }
             State::STARTING => {
@@ -324,6 +476,17 @@ impl Proc {
                 self.loop_action_true = None;
                 self.loop_action_false = None
             }
+            State::SUCCEEDED => {
+                self.loop_test_file_config = None;
+                self.loop_action_true = None;
+                self.loop_action_false = None
+            }
+            State::WAITING | State::QUEUED => {
+                // handled by the supervisor
+                self.loop_test_file_config = None;
+                self.loop_action_true = None;
+                self.loop_action_false = None
+            }
             State::UNKNOWN => {
                 self.loop_test_file_config = None;
                 self.loop_action_true = None;
@@ -352,4 +515,115 @@ impl Proc {
             }
         }
     }
+
+    pub fn setup_command_notify(&mut self, parse_file: &File) {
+        if let Some(secs) = parse_file.watchdog_sec {
+            if secs <= 0 {
+                println!(
+                    "{}: ignoring watchdog_sec {} (must be at least 1)",
+                    self.get_name(),
+                    secs
+                );
+                return;
+            }
+            match NotifySocket::bind(&self.get_name(), user_owner(parse_file)) {
+                Ok(socket) => {
+                    if let Some(command) = &mut self.command {
+                        command.env("NOTIFY_SOCKET", &socket.path);
+                        command.env("WATCHDOG_USEC", (secs * 1_000_000).to_string());
+                    }
+                    self.watchdog = Some(Duration::from_secs(secs as u64));
+                    self.notify = Some(socket);
+                }
+                Err(e) => println!("{}: can't create notify socket: {e}", self.get_name()),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::task::Task;
+    use yaml_rust::YamlLoader;
+
+    fn task(autorestart: &str) -> Task {
+        let config = format!(
+            "name: auto\ncommand: /bin/true\nautorestart: {}\nexitcodes: [0, 2]\nstartretries: 2\nstdout: /dev/null\nstderr: /dev/null\n",
+            autorestart
+        );
+        let mut task = Task::new(&YamlLoader::load_from_str(&config).unwrap()[0]);
+        task.parse_file.init_args();
+        task.set_command("auto".to_string());
+        task
+    }
+
+    // as the supervisor does when it reaps the process
+    fn exit(task: &mut Task, state: State, status: i32) -> State {
+        let proc = &mut task.process_lst[0];
+
+        proc.state = state;
+        proc.test_autorestart(task.parse_file.clone(), ExitStatus::from_raw(status));
+        if let Some(mut child) = proc.child.take() {
+            let _ = child.wait();
+        }
+        proc.state.clone()
+    }
+
+    #[test]
+    fn unexpected_only_restarts_on_unexpected_exits() {
+        let mut task = task("unexpected");
+
+        assert_eq!(exit(&mut task, State::EXITED, 2 << 8), State::EXITED);
+        assert_eq!(exit(&mut task, State::EXITED, 1 << 8), State::STARTING);
+        // SIGTERM is the stopsignal, SIGKILL is not
+        assert_eq!(exit(&mut task, State::EXITED, 15), State::EXITED);
+        assert_eq!(exit(&mut task, State::EXITED, 9), State::STARTING);
+    }
+
+    #[test]
+    fn stopped_on_request_is_not_restarted() {
+        let mut task = task("true");
+
+        assert_eq!(exit(&mut task, State::STOPPED, 15), State::STOPPED);
+    }
+
+    #[test]
+    fn fatal_after_startretries() {
+        let mut task = task("true");
+
+        assert_eq!(exit(&mut task, State::EXITED, 1 << 8), State::STARTING);
+        assert_eq!(exit(&mut task, State::EXITED, 1 << 8), State::STARTING);
+        assert_eq!(exit(&mut task, State::EXITED, 1 << 8), State::FATAL);
+        assert_eq!(task.process_lst[0].nbr_restart, 2);
+    }
+
+    #[test]
+    fn running_only_after_starttime() {
+        let mut task = task("true");
+        let proc = &mut task.process_lst[0];
+
+        proc.state = State::STARTING;
+        proc.spawned_time = Some(Instant::now());
+        proc.exp_duration = Duration::from_secs(60);
+        proc.check_starting();
+        assert_eq!(proc.state, State::STARTING);
+
+        proc.exp_duration = Duration::ZERO;
+        proc.check_starting();
+        assert_eq!(proc.state, State::RUNNING);
+    }
+
+    #[test]
+    fn exit_once_running_resets_start_retries() {
+        let mut task = task("true");
+
+        assert_eq!(exit(&mut task, State::EXITED, 1 << 8), State::STARTING);
+        assert_eq!(exit(&mut task, State::EXITED, 1 << 8), State::STARTING);
+        task.process_lst[0].state = State::RUNNING;
+        task.process_lst[0].reset_start_retries();
+        assert_eq!(exit(&mut task, State::EXITED, 1 << 8), State::STARTING);
+        assert_eq!(exit(&mut task, State::EXITED, 1 << 8), State::STARTING);
+        assert_eq!(exit(&mut task, State::EXITED, 1 << 8), State::FATAL);
+    }
 }
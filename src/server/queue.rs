@@ -0,0 +1,60 @@
+use std::time::Instant;
+
+use super::conf::Taskmaster;
+use super::loop_exec::LoopRestart;
+use super::proc::Proc;
+use super::tool::random_duration;
+use share_structures::State;
+
+impl Proc {
+    /// Queue the process instead of spawning it right away: instance N waits
+    /// N * start_stagger (plus a random splay) and then for a free start slot.
+    pub fn enqueue(&mut self) {
+        let delay = self.start_stagger * self.instance as u32 + random_duration(self.start_splay);
+
+        self.queued_until = Some(Instant::now() + delay);
+        self.change_state(State::QUEUED);
+    }
+
+    pub fn queued_description(&self) -> String {
+        match self.queued_until {
+            Some(until) if until > Instant::now() => {
+                format!("starting in {}s", (until - Instant::now()).as_secs() + 1)
+            }
+            _ => "waiting for a start slot".to_string(),
+        }
+    }
+}
+
+impl Taskmaster {
+    pub fn starting_count(&self) -> usize {
+        self.task_lst
+            .iter()
+            .flat_map(|task| task.process_lst.iter())
+            .filter(|proc| proc.state == State::STARTING)
+            .count()
+    }
+
+    /// Start the queued processes whose delay is over, as long as fewer than
+    /// max_concurrent_starts processes are STARTING.
+    pub fn dispatch_queued(&mut self) {
+        let mut starting = self.starting_count();
+        let max = self.max_concurrent_starts.unwrap_or(usize::MAX);
+        let now = Instant::now();
+
+        for task in &mut self.task_lst {
+            for proc in &mut task.process_lst {
+                if starting >= max {
+                    return;
+                }
+                if proc.state == State::QUEUED
+                    && proc.queued_until.map(|t| t <= now).unwrap_or(true)
+                {
+                    proc.queued_until = None;
+                    proc.start_loop();
+                    starting += 1;
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,140 @@
+use chrono::Local;
+use std::time::Instant;
+use yaml_rust::Yaml;
+
+use super::conf::Taskmaster;
+use super::task::Task;
+use share_structures::State;
+
+// `conflicts: x` or `conflicts: [x, y]`
+pub fn parse_name_list(yaml: &Yaml) -> Vec<String> {
+    match yaml.as_str() {
+        Some(name) => vec![name.to_string()],
+        None => yaml
+            .clone()
+            .into_iter()
+            .filter_map(|value| value.as_str().map(|name| name.to_string()))
+            .collect(),
+    }
+}
+
+impl Task {
+    pub fn is_running(&self) -> bool {
+        self.process_lst
+            .iter()
+            .any(|proc| proc.state == State::RUNNING || proc.state == State::STARTING)
+    }
+
+    pub fn is_fatal(&self) -> bool {
+        self.process_lst
+            .iter()
+            .any(|proc| proc.state == State::FATAL && !proc.retiring)
+    }
+
+    // most recent start among the running instances
+    pub fn last_start(&self) -> Option<Instant> {
+        self.process_lst
+            .iter()
+            .filter(|proc| proc.state == State::RUNNING || proc.state == State::STARTING)
+            .filter_map(|proc| proc.started_time)
+            .max()
+    }
+
+    pub fn conflicts_with(&self, other: &Task) -> bool {
+        let (name, other_name) = (self.getnameparse(), other.getnameparse());
+
+        name != other_name
+            && (self.parse_file.conflicts.contains(other_name)
+                || other.parse_file.conflicts.contains(name))
+    }
+}
+
+impl Taskmaster {
+    /// Name of a running program that conflicts with the program of `proc_name`.
+    pub fn running_conflict(&self, proc_name: &str) -> Option<String> {
+        let task = self.task_lst.iter().find(|task| {
+            task.process_lst
+                .iter()
+                .any(|proc| proc.get_name() == proc_name)
+        })?;
+
+        self.task_lst
+            .iter()
+            .find(|other| other.is_running() && task.conflicts_with(other))
+            .map(|other| other.getnameparse().clone())
+    }
+
+    /// Called by the supervisor: start/stop fallbacks and stop conflicting programs.
+    pub fn enforce_relations(&mut self) {
+        self.enforce_fallbacks();
+        self.enforce_conflicts();
+    }
+
+    fn enforce_fallbacks(&mut self) {
+        let mut to_start = vec![];
+        let mut to_stop = vec![];
+
+        for task in &mut self.task_lst {
+            if let Some(fallback) = &task.parse_file.fallback {
+                if task.is_fatal() && !task.fallback_started {
+                    println!(
+                        "{} INFO fallback: {} is FATAL, starting {}",
+                        Local::now().format("%d/%m/%Y %H:%M:%S"),
+                        task.getnameparse(),
+                        fallback
+                    );
+                    task.fallback_started = true;
+                    to_start.push(fallback.clone());
+                } else if !task.is_fatal() && task.is_running() && task.fallback_started {
+                    println!(
+                        "{} INFO fallback: {} recovered, stopping {}",
+                        Local::now().format("%d/%m/%Y %H:%M:%S"),
+                        task.getnameparse(),
+                        fallback
+                    );
+                    task.fallback_started = false;
+                    to_stop.push(fallback.clone());
+                }
+            }
+        }
+        for name in to_start {
+            match self.get_task_by_name(&Some(name.clone())) {
+                Ok(task) => task.start_all_proc(),
+                Err(e) => println!("fallback {}: {}", name, e),
+            }
+        }
+        for name in to_stop {
+            if let Ok(task) = self.get_task_by_name(&Some(name)) {
+                task.stop_all_process();
+            }
+        }
+    }
+
+    fn enforce_conflicts(&mut self) {
+        let mut to_stop = vec![];
+
+        for task in &self.task_lst {
+            for other in &self.task_lst {
+                // the program started last wins, the other one is stopped
+                if task.is_running()
+                    && other.is_running()
+                    && task.conflicts_with(other)
+                    && other.last_start() < task.last_start()
+                {
+                    println!(
+                        "{} INFO conflict: {} started, stopping {}",
+                        Local::now().format("%d/%m/%Y %H:%M:%S"),
+                        task.getnameparse(),
+                        other.getnameparse()
+                    );
+                    to_stop.push(other.getnameparse().clone());
+                }
+            }
+        }
+        for name in to_stop {
+            if let Ok(task) = self.get_task_by_name(&Some(name)) {
+                task.stop_all_process();
+            }
+        }
+    }
+}
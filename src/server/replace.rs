@@ -0,0 +1,205 @@
+use chrono::Local;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use super::conf::Taskmaster;
+use super::file::Files;
+use super::proc::Proc;
+use super::task::Task;
+use share_structures::{CallOn, Response, State};
+
+// how often the waiting client looks at the replacement
+const WAIT_POLL: Duration = Duration::from_millis(200);
+
+/// `restart --replace <proc>`: the next generation runs next to the old one
+/// until it is RUNNING, then the old one is stopped.
+#[derive(Debug, Clone)]
+pub struct Replacement {
+    pub old: String,
+    pub new: String,
+    // the old generation was asked to stop
+    stopping: bool,
+    pub progress: Vec<String>,
+    // Some once the new generation took over, or once it failed
+    pub result: Option<Result<(), ()>>,
+}
+
+impl Task {
+    fn report_replace(&mut self, line: String) {
+        println!(
+            "{} INFO replace: {}",
+            Local::now().format("%d/%m/%Y %H:%M:%S"),
+            line
+        );
+        if let Some(replacing) = &mut self.replacing {
+            replacing.progress.push(line);
+        }
+    }
+
+    fn find_proc(&self, name: &str) -> Option<usize> {
+        self.process_lst
+            .iter()
+            .position(|proc| proc.get_name() == name && !proc.retiring)
+    }
+
+    // the next generation of `old`, set up like it and logging to the same files
+    fn next_generation(&self, old: &Proc) -> Proc {
+        let generation = old.generation + 1;
+        let name = format!("{}@{}", old.get_name(), generation);
+        let mut file_log = old.s_file.clone();
+        file_log.name = Some(name.clone());
+        let mut new_proc = Proc::new(self.new_command(), file_log, Some(name));
+
+        new_proc.redirection();
+        self.setup_proc(&mut new_proc, old.instance);
+        new_proc.generation = generation;
+        new_proc
+    }
+
+    /// Move the replacement on: stop the old generation once the new one is
+    /// RUNNING, and hand the name over once the old one is gone.
+    pub fn step_replace(&mut self) {
+        let mut replacing = match self.replacing.take() {
+            Some(replacing) if replacing.result.is_none() => replacing,
+            other => {
+                self.replacing = other;
+                return;
+            }
+        };
+        let mut lines = vec![];
+        let (old, new) = (replacing.old.clone(), replacing.new.clone());
+
+        match self.find_proc(&new) {
+            // removed by a reload meanwhile
+            None => {
+                lines.push(format!("{} is gone, aborting", new));
+                replacing.result = Some(Err(()));
+            }
+            Some(index) => {
+                let old_index = self.find_proc(&old);
+                let proc = &mut self.process_lst[index];
+
+                let down = proc.child.is_none()
+                    && matches!(proc.state, State::EXITED | State::STOPPED);
+
+                if proc.state == State::FATAL || down {
+                    lines.push(format!(
+                        "{} is {:?} ({}), {} keeps running",
+                        new,
+                        proc.state,
+                        proc.last_outcome(),
+                        old
+                    ));
+                    proc.retire();
+                    replacing.result = Some(Err(()));
+                } else if proc.state == State::RUNNING && !replacing.stopping {
+                    lines.push(format!(
+                        "{} RUNNING, pid {}, stopping {}",
+                        new,
+                        proc.pid.unwrap_or(0),
+                        old
+                    ));
+                    if let Some(old_index) = old_index {
+                        let old_proc = &mut self.process_lst[old_index];
+                        old_proc.retire();
+                        old_proc.description = format!("replaced by {}", new);
+                    }
+                    replacing.stopping = true;
+                } else if replacing.stopping
+                    && !self.process_lst.iter().any(|proc| proc.get_name() == old)
+                {
+                    // the old generation was dropped from the program
+                    self.process_lst[index].name = Some(old.clone());
+                    lines.push(format!("{} took over as {}", new, old));
+                    replacing.result = Some(Ok(()));
+                }
+            }
+        }
+        self.replacing = Some(replacing);
+        for line in lines {
+            self.report_replace(line);
+        }
+    }
+}
+
+impl Taskmaster {
+    pub fn replace(&mut self, name: String) -> Response {
+        let res = match self
+            .task_lst
+            .iter_mut()
+            .find(|task| task.process_lst.iter().any(|proc| proc.get_name() == name))
+        {
+            None => Err(format!("{}: ERROR (no such process)", name)),
+            Some(task) if task.replacing.as_ref().map(|r| r.result.is_none()) == Some(true) => {
+                Err(format!("{}: ERROR (replacement in progress)", name))
+            }
+            Some(task) => match task.find_proc(&name) {
+                Some(index) if task.process_lst[index].state == State::RUNNING => {
+                    let mut new_proc = task.next_generation(&task.process_lst[index]);
+                    let new = new_proc.get_name();
+
+                    match new_proc.start() {
+                        Ok(_) => {
+                            task.process_lst.insert(index + 1, new_proc);
+                            task.replacing = Some(Replacement {
+                                old: name.clone(),
+                                new: new.clone(),
+                                stopping: false,
+                                progress: vec![],
+                                result: None,
+                            });
+                            task.report_replace(format!("starting {} next to {}", new, name));
+                            Ok(format!("{}: replacing with {}", name, new))
+                        }
+                        Err(e) => Err(e),
+                    }
+                }
+                _ => Err(format!("{}: ERROR (not running)", name)),
+            },
+        };
+
+        Response {
+            cmd: CallOn::Replace(name),
+            content: self.get_proc_list(),
+            success: res,
+        }
+    }
+
+    pub fn step_replacements(&mut self) {
+        for task in &mut self.task_lst {
+            task.step_replace();
+        }
+    }
+}
+
+/// Hold the client until the new generation took over from `name`, or
+/// failed to.
+pub fn wait_replace(taskmaster: &Arc<Mutex<Taskmaster>>, name: String) -> Response {
+    loop {
+        thread::sleep(WAIT_POLL);
+        let mut app = taskmaster.lock().unwrap();
+        let task = app.task_lst.iter_mut().find(|task| {
+            task.replacing.as_ref().map(|replacing| &replacing.old) == Some(&name)
+        });
+        let replacing = match task {
+            Some(task) => match &task.replacing {
+                Some(replacing) if replacing.result.is_some() => task.replacing.take(),
+                _ => continue,
+            },
+            None => None,
+        };
+        let res = match replacing {
+            Some(replacing) => match replacing.result {
+                Some(Ok(())) => Ok(replacing.progress.join("\n\r")),
+                _ => Err(replacing.progress.join("\n\r")),
+            },
+            None => Err(format!("{}: ERROR (removed by a reload)", name)),
+        };
+        return Response {
+            cmd: CallOn::Replace(name),
+            content: app.get_proc_list(),
+            success: res,
+        };
+    }
+}
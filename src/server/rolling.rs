@@ -0,0 +1,263 @@
+use bincode::serialize;
+use chrono::Local;
+use std::collections::VecDeque;
+use std::io::{self, Write};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use super::conf::Taskmaster;
+use super::proc::Proc;
+use super::task::Task;
+use share_structures::{CallOn, Response, State};
+
+// how often the waiting client looks at the rolling restart
+const WAIT_POLL: Duration = Duration::from_millis(200);
+
+/// `restart --rolling <program> [batch]`, driven by the supervisor.
+#[derive(Debug, Clone)]
+pub struct RollingRestart {
+    pub batch: usize,
+    pending: VecDeque<String>,
+    // instances restarted and not healthy yet
+    current: Vec<String>,
+    total: usize,
+    done: usize,
+    pub progress: Vec<String>,
+    // Some once every instance is back, or once one went FATAL
+    pub result: Option<Result<(), ()>>,
+}
+
+impl Proc {
+    fn restart_gracefully(&mut self) -> Result<String, String> {
+        match self.state {
+            State::RUNNING => {
+                self.restart_on_stop = true;
+                self.stop()
+            }
+            _ => self.start(),
+        }
+    }
+}
+
+impl Task {
+    fn report(&mut self, line: String) {
+        println!(
+            "{} INFO rolling restart: {}",
+            Local::now().format("%d/%m/%Y %H:%M:%S"),
+            line
+        );
+        if let Some(rolling) = &mut self.rolling {
+            rolling.progress.push(line);
+        }
+    }
+
+    /// Move the rolling restart of the program on: check the batch in
+    /// flight, then restart the next one.
+    pub fn step_rolling(&mut self) {
+        let mut rolling = match self.rolling.take() {
+            Some(rolling) if rolling.result.is_none() => rolling,
+            other => {
+                self.rolling = other;
+                return;
+            }
+        };
+        let mut lines = vec![];
+
+        for name in std::mem::take(&mut rolling.current) {
+            let proc = self
+                .process_lst
+                .iter()
+                .find(|proc| proc.get_name() == name && !proc.retiring);
+            match proc {
+                Some(proc) if proc.state == State::FATAL => {
+                    lines.push(format!(
+                        "{} is FATAL ({}), aborting after {}/{} instances",
+                        name,
+                        proc.last_outcome(),
+                        rolling.done,
+                        rolling.total
+                    ));
+                    rolling.result = Some(Err(()));
+                }
+                // exited and not brought back by autorestart, or stopped by hand
+                Some(proc)
+                    if proc.child.is_none()
+                        && matches!(proc.state, State::EXITED | State::STOPPED) =>
+                {
+                    lines.push(format!(
+                        "{} is {:?} ({}), aborting after {}/{} instances",
+                        name,
+                        proc.state,
+                        proc.last_outcome(),
+                        rolling.done,
+                        rolling.total
+                    ));
+                    rolling.result = Some(Err(()));
+                }
+                // RUNNING once up for starttime
+                Some(proc) if proc.state != State::RUNNING => rolling.current.push(name),
+                Some(proc) => {
+                    rolling.done += 1;
+                    lines.push(format!(
+                        "{} RUNNING, pid {} ({}/{})",
+                        name,
+                        proc.pid.unwrap_or(0),
+                        rolling.done,
+                        rolling.total
+                    ));
+                }
+                // scaled down or removed by a reload meanwhile
+                None => rolling.total -= 1,
+            }
+        }
+        if rolling.result.is_none() && rolling.current.is_empty() {
+            match rolling.pending.is_empty() {
+                true => {
+                    lines.push(format!(
+                        "{} done ({}/{})",
+                        self.getnameparse(),
+                        rolling.done,
+                        rolling.total
+                    ));
+                    rolling.result = Some(Ok(()));
+                }
+                false => {
+                    let count = rolling.batch.min(rolling.pending.len());
+                    for name in rolling.pending.drain(..count).collect::<Vec<String>>() {
+                        match self
+                            .process_lst
+                            .iter_mut()
+                            .find(|proc| proc.get_name() == name && !proc.retiring)
+                        {
+                            Some(proc) => {
+                                let _ = proc.restart_gracefully();
+                                lines.push(format!("restarting {}", name));
+                                rolling.current.push(name);
+                            }
+                            None => rolling.total -= 1,
+                        }
+                    }
+                }
+            }
+        }
+        self.rolling = Some(rolling);
+        for line in lines {
+            self.report(line);
+        }
+    }
+}
+
+impl Taskmaster {
+    pub fn rolling_restart(&mut self, name: String, batch: usize) -> Response {
+        let res = match self
+            .task_lst
+            .iter_mut()
+            .find(|task| task.getnameparse() == &name)
+        {
+            None => Err(format!("{}: ERROR (no such program)", name)),
+            Some(task) if task.rolling.as_ref().map(|r| r.result.is_none()) == Some(true) => {
+                Err(format!("{}: ERROR (rolling restart in progress)", name))
+            }
+            Some(task) => {
+                // scaled down ones are on their way out
+                let pending: VecDeque<String> = task
+                    .process_lst
+                    .iter()
+                    .filter(|proc| !proc.retiring)
+                    .map(|proc| proc.get_name())
+                    .collect();
+                task.rolling = Some(RollingRestart {
+                    batch: batch.max(1),
+                    total: pending.len(),
+                    pending,
+                    current: vec![],
+                    done: 0,
+                    progress: vec![],
+                    result: None,
+                });
+                task.step_rolling();
+                Ok(format!("{}: rolling restart started", name))
+            }
+        };
+
+        Response {
+            cmd: CallOn::RollingRestart(name, batch),
+            content: self.get_proc_list(),
+            success: res,
+        }
+    }
+
+    pub fn step_rolling_restarts(&mut self) {
+        for task in &mut self.task_lst {
+            task.step_rolling();
+        }
+    }
+}
+
+/// Hold the client until the rolling restart of `name` is over, sending it
+/// each progress line as it comes. The restart goes on if the client leaves.
+pub fn wait_rolling(
+    taskmaster: &Arc<Mutex<Taskmaster>>,
+    name: String,
+    batch: usize,
+    stream: &mut impl Write,
+) -> Response {
+    let mut sent = 0;
+    let mut client_gone = false;
+
+    loop {
+        thread::sleep(WAIT_POLL);
+        let mut app = taskmaster.lock().unwrap();
+        let task = app
+            .task_lst
+            .iter_mut()
+            .find(|task| task.getnameparse() == &name);
+        // the new lines, and Some once over: the rolling restart, or None if the program is gone
+        let (lines, rolling) = match task {
+            Some(task) => match &task.rolling {
+                Some(rolling) => {
+                    let lines = rolling.progress[sent.min(rolling.progress.len())..].to_vec();
+                    match rolling.result.is_some() {
+                        true => (lines, Some(task.rolling.take())),
+                        false => (lines, None),
+                    }
+                }
+                None => (vec![], Some(None)),
+            },
+            None => (vec![], Some(None)),
+        };
+        sent += lines.len();
+        if !client_gone {
+            client_gone = send_progress(stream, &lines).is_err();
+        }
+        let res = match rolling {
+            None if client_gone => Err(format!("{}: client gone", name)),
+            None => continue,
+            Some(Some(rolling)) => match rolling.result {
+                Some(Ok(())) => Ok(format!("{}: rolling restart done", name)),
+                _ => Err(format!("{}: rolling restart aborted", name)),
+            },
+            Some(None) => Err(format!("{}: ERROR (removed by a reload)", name)),
+        };
+        return Response {
+            cmd: CallOn::RollingRestart(name, batch),
+            content: app.get_proc_list(),
+            success: res,
+        };
+    }
+}
+
+// each line in a Progress response, ahead of the final one
+fn send_progress(stream: &mut impl Write, lines: &[String]) -> io::Result<()> {
+    for line in lines {
+        let progress = Response {
+            cmd: CallOn::Progress,
+            success: Ok(line.clone()),
+            content: vec![],
+        };
+        let progress = serialize(&progress).map_err(io::Error::other)?;
+        stream.write_all(&progress)?;
+    }
+    Ok(())
+}
@@ -0,0 +1,55 @@
+use nix::unistd::geteuid;
+use std::env;
+use std::fs::{self, DirBuilder};
+use std::os::unix::fs::{DirBuilderExt, MetadataExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+
+const ROOT_RUNTIME_DIR: &str = "/run/taskmaster";
+
+/// Where the daemon keeps its runtime files (private tmp dirs, notify
+/// sockets): /run/taskmaster as root, $XDG_RUNTIME_DIR/taskmaster otherwise.
+pub fn runtime_dir() -> PathBuf {
+    if geteuid().is_root() {
+        return PathBuf::from(ROOT_RUNTIME_DIR);
+    }
+    match env::var_os("XDG_RUNTIME_DIR") {
+        Some(dir) => Path::new(&dir).join("taskmaster"),
+        None => env::temp_dir().join(format!("taskmaster-{}", geteuid())),
+    }
+}
+
+/// Anyone else able to write there could plant links or files the daemon
+/// would then follow or trust.
+pub fn check_private(path: &Path) -> Result<(), String> {
+    let meta = fs::symlink_metadata(path).map_err(|e| format!("{}: {e}", path.display()))?;
+
+    if meta.file_type().is_symlink() {
+        Err(format!("{} is a symlink", path.display()))
+    } else if meta.uid() != geteuid().as_raw() {
+        Err(format!("{} is not owned by uid {}", path.display(), geteuid()))
+    } else if meta.mode() & 0o022 != 0 {
+        Err(format!("{} is writable by others", path.display()))
+    } else {
+        Ok(())
+    }
+}
+
+/// `<runtime dir>/<name>`, refused unless it and the runtime dir are the
+/// daemon's own. Others can go through them but not list them: a program
+/// running as another user reaches its own entry, by a name it can't guess.
+pub fn private_dir(name: &str) -> Result<PathBuf, String> {
+    let base = runtime_dir();
+    let dir = base.join(name);
+
+    DirBuilder::new()
+        .recursive(true)
+        .mode(0o700)
+        .create(&dir)
+        .map_err(|e| format!("can't create {}: {e}", dir.display()))?;
+    for path in [&base, &dir] {
+        check_private(path)?;
+        fs::set_permissions(path, fs::Permissions::from_mode(0o711))
+            .map_err(|e| format!("can't chmod {}: {e}", path.display()))?;
+    }
+    Ok(dir)
+}
@@ -0,0 +1,94 @@
+use nix::libc;
+use std::io;
+use std::os::unix::process::ExitStatusExt;
+use std::process::ExitStatus;
+use std::time::Duration;
+
+use super::memory::size_to_string;
+use super::proc::Proc;
+use super::tool::DurationDate;
+
+/// What a run of a process cost, from wait4.
+#[derive(Debug, Clone, Default)]
+pub struct Usage {
+    pub runtime: Duration,
+    pub user_time: Duration,
+    pub system_time: Duration,
+    // bytes
+    pub max_rss: u64,
+    pub voluntary_switches: i64,
+    pub involuntary_switches: i64,
+}
+
+fn timeval_to_duration(time: libc::timeval) -> Duration {
+    Duration::new(time.tv_sec as u64, time.tv_usec as u32 * 1000)
+}
+
+impl Usage {
+    fn from_rusage(rusage: &libc::rusage, runtime: Duration) -> Self {
+        Usage {
+            runtime,
+            user_time: timeval_to_duration(rusage.ru_utime),
+            system_time: timeval_to_duration(rusage.ru_stime),
+            max_rss: rusage.ru_maxrss as u64 * 1024,
+            voluntary_switches: rusage.ru_nvcsw,
+            involuntary_switches: rusage.ru_nivcsw,
+        }
+    }
+
+    pub fn to_details(&self) -> String {
+        format!(
+            "ran {}, user {:.2}s, system {:.2}s, max rss {}, context switches {}/{}",
+            self.runtime.durationdate(),
+            self.user_time.as_secs_f64(),
+            self.system_time.as_secs_f64(),
+            size_to_string(self.max_rss),
+            self.voluntary_switches,
+            self.involuntary_switches
+        )
+    }
+}
+
+impl Proc {
+    fn reap(&mut self, options: libc::c_int) -> io::Result<Option<ExitStatus>> {
+        let pid = match &self.child {
+            Some(child) => child.id() as libc::pid_t,
+            None => return Ok(None),
+        };
+        // the child is only waited for once, later calls get the same status
+        if let Some((reaped_pid, status, _)) = &self.reaped {
+            if *reaped_pid == pid {
+                return Ok(Some(*status));
+            }
+        }
+        let mut status = 0;
+        let mut rusage: libc::rusage = unsafe { std::mem::zeroed() };
+
+        match unsafe { libc::wait4(pid, &mut status, options, &mut rusage) } {
+            -1 => Err(io::Error::last_os_error()),
+            0 => Ok(None),
+            _ => {
+                let status = ExitStatus::from_raw(status);
+                let runtime = self
+                    .spawned_time
+                    .map(|time| time.elapsed())
+                    .unwrap_or_default();
+                self.reaped = Some((pid, status, Usage::from_rusage(&rusage, runtime)));
+                Ok(Some(status))
+            }
+        }
+    }
+
+    /// `Child::try_wait` that keeps the rusage of the run.
+    pub fn try_reap(&mut self) -> io::Result<Option<ExitStatus>> {
+        self.reap(libc::WNOHANG)
+    }
+
+    pub fn wait_reap(&mut self) -> io::Result<Option<ExitStatus>> {
+        self.reap(0)
+    }
+
+    pub fn last_usage(&self) -> Option<Usage> {
+        self.reaped.as_ref().map(|(_, _, usage)| usage.clone())
+    }
+}
@@ -0,0 +1,293 @@
+use chrono::Local;
+use nix::unistd::Pid;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::time::Instant;
+use yaml_rust::{Yaml, YamlEmitter, YamlLoader};
+
+use super::conf::Taskmaster;
+use super::proc::Proc;
+use super::task::Task;
+use share_structures::{CallOn, Response, State};
+
+impl Proc {
+    /// Stop the process for good, it is dropped from its program once down.
+    pub fn retire(&mut self) {
+        self.retiring = true;
+        self.restart_on_stop = false;
+        match (&self.child, &self.state) {
+            (Some(child), State::RUNNING | State::STARTING) => {
+                let pid = Pid::from_raw(child.id().try_into().unwrap());
+                // stopwaitsecs counts from here, then it is killed
+                self.started_time = Some(Instant::now());
+                self.change_state(State::STOPPING);
+                let _ = nix::sys::signal::kill(pid, self.stopsignal);
+            }
+            // already STOPPING
+            (Some(_), _) => {}
+            (None, _) => self.change_to_stopped(),
+        }
+    }
+
+    // scaled back up before it was down
+    fn revive(&mut self) {
+        self.retiring = false;
+        self.restart_on_stop = self.state == State::STOPPING;
+    }
+}
+
+impl Task {
+    /// Instances not on their way out.
+    pub fn active_count(&self) -> i64 {
+        self.process_lst.iter().filter(|proc| !proc.retiring).count() as i64
+    }
+
+    /// Take back the lowest-numbered retiring instances, return how many.
+    pub fn revive_nb_process(&mut self, to_be_add: i64) -> i64 {
+        let mut revived = 0;
+
+        for proc in self.process_lst.iter_mut().filter(|proc| proc.retiring) {
+            if revived == to_be_add {
+                break;
+            }
+            proc.revive();
+            revived += 1;
+        }
+        revived
+    }
+
+    fn scale(&mut self, numprocs: i64) -> String {
+        let current = self.active_count();
+
+        self.parse_file.numprocs = Some(numprocs);
+        if numprocs < current {
+            let names: Vec<String> = self.process_lst[numprocs as usize..current as usize]
+                .iter()
+                .map(|proc| proc.get_name())
+                .collect();
+            self.remove_nb_process(current - numprocs);
+            format!("stopping {}", names.join(", "))
+        } else {
+            self.add_nb_process(numprocs - current);
+            let added = &mut self.process_lst[current as usize..numprocs as usize];
+            for proc in added.iter_mut() {
+                if proc.state == State::STOPPED || proc.state == State::EXITED {
+                    proc.enqueue();
+                }
+            }
+            let names: Vec<String> = added
+                .iter()
+                .map(|proc| proc.get_name())
+                .collect();
+            format!("starting {}", names.join(", "))
+        }
+    }
+}
+
+impl Taskmaster {
+    /// `scale <program> <numprocs> [--save]`
+    pub fn scale(&mut self, name: String, numprocs: usize, save: bool) -> Response {
+        let res = match self
+            .task_lst
+            .iter_mut()
+            .find(|task| task.getnameparse() == &name)
+        {
+            None => Err(format!("{}: ERROR (no such program)", name)),
+            Some(task) if task.replacing.as_ref().map(|r| r.result.is_none()) == Some(true) => {
+                Err(format!("{}: ERROR (replacement in progress)", name))
+            }
+            Some(task) if task.active_count() == numprocs as i64 => {
+                Ok(format!("{}: already at {} instances", name, numprocs))
+            }
+            Some(task) => {
+                let current = task.active_count();
+                let change = task.scale(numprocs as i64);
+                println!(
+                    "{} INFO scale: {} from {} to {} instances, {}",
+                    Local::now().format("%d/%m/%Y %H:%M:%S"),
+                    name,
+                    current,
+                    numprocs,
+                    change
+                );
+                self.nprocs += numprocs as i64 - current;
+                Ok(format!("{}: scaled to {} ({})", name, numprocs, change))
+            }
+        };
+        let res = match (res, save) {
+            (Ok(scaled), true) => match self.save_numprocs(&name, numprocs as i64) {
+                Ok(()) => Ok(format!("{}, saved to {}", scaled, self.config.path)),
+                Err(e) => Err(format!("{}, not saved ({})", scaled, e)),
+            },
+            (Ok(scaled), false) => {
+                // until the next reload
+                self.scaled = true;
+                Ok(scaled)
+            }
+            (res, _) => res,
+        };
+
+        Response {
+            cmd: CallOn::Scale(name, numprocs, save),
+            content: self.get_proc_list(),
+            success: res,
+        }
+    }
+
+    // write numprocs of the program back to the config file
+    fn save_numprocs(&mut self, name: &str, numprocs: i64) -> Result<(), String> {
+        let mut yaml = self.config.yaml.clone();
+        if !set_numprocs(&mut yaml, name, numprocs) {
+            return Err(format!("{} not in {}", name, self.config.path));
+        }
+
+        let text = fs::read_to_string(&self.config.path).map_err(|e| format!("{}", e))?;
+        // only that line changed, unless the file isn't laid out the usual way
+        let edited = edit_numprocs(&text, name, numprocs).filter(|out| {
+            // set again to compare, the key may be placed elsewhere
+            let doc = YamlLoader::load_from_str(out)
+                .ok()
+                .and_then(|docs| docs.into_iter().next());
+            doc.map(|mut doc| set_numprocs(&mut doc, name, numprocs) && doc == yaml) == Some(true)
+        });
+        let out = match edited {
+            Some(out) => out,
+            None => {
+                println!(
+                    "{}: numprocs of {} rewritten with the whole file, its comments are lost",
+                    self.config.path, name
+                );
+                let mut out = String::new();
+                YamlEmitter::new(&mut out)
+                    .dump(&yaml)
+                    .map_err(|e| format!("{:?}", e))?;
+                out.push('\n');
+                out
+            }
+        };
+        write_aside(&self.config.path, &out).map_err(|e| format!("{}", e))?;
+        self.config.yaml = yaml;
+        Ok(())
+    }
+}
+
+fn set_numprocs(yaml: &mut Yaml, name: &str, numprocs: i64) -> bool {
+    let program = match yaml {
+        Yaml::Hash(config) => match config.get_mut(&Yaml::String("programs".to_string())) {
+            Some(Yaml::Array(programs)) => programs
+                .iter_mut()
+                .find(|program| program["name"].as_str() == Some(name)),
+            _ => None,
+        },
+        _ => None,
+    };
+    match program {
+        Some(Yaml::Hash(program)) => {
+            program.insert(
+                Yaml::String("numprocs".to_string()),
+                Yaml::Integer(numprocs),
+            );
+            true
+        }
+        _ => false,
+    }
+}
+
+fn indent(line: &str) -> usize {
+    line.len() - line.trim_start().len()
+}
+
+// where the key of the line starts, after the list dash if any
+fn key_column(line: &str) -> usize {
+    line.len() - line.trim_start().trim_start_matches("- ").len()
+}
+
+// `key: value # comment` as key and value
+fn key_value(line: &str) -> Option<(&str, &str)> {
+    let (key, value) = line.trim_start().trim_start_matches("- ").split_once(':')?;
+    let value = value.split(" #").next().unwrap_or_default().trim();
+
+    Some((key.trim(), value.trim_matches(|c| c == '"' || c == '\'')))
+}
+
+/// The config text with the numprocs of program `name` set, every other line
+/// as it was. None if the program can't be found that way.
+fn edit_numprocs(text: &str, name: &str, numprocs: i64) -> Option<String> {
+    let mut lines: Vec<String> = text.lines().map(|line| line.to_string()).collect();
+    let at = lines
+        .iter()
+        .position(|line| key_value(line) == Some(("name", name)))?;
+    let keys = key_column(&lines[at]);
+    // from the dash of the program to its last line
+    let start = (0..=at)
+        .rev()
+        .find(|i| lines[*i].trim_start().starts_with("- ") && key_column(&lines[*i]) == keys)?;
+    let end = at
+        + 1
+        + lines[at + 1..]
+            .iter()
+            .take_while(|line| {
+                line.trim().is_empty() || line.trim_start().starts_with('#') || indent(line) >= keys
+            })
+            .count();
+    let existing = (start..end).find(|i| {
+        key_column(&lines[*i]) == keys
+            && key_value(&lines[*i]).map(|(key, _)| key) == Some("numprocs")
+    });
+
+    match existing {
+        Some(i) => {
+            let (key, rest) = lines[i].split_once(':')?;
+            let comment = rest.find(" #").map(|at| &rest[at..]).unwrap_or_default();
+            lines[i] = format!("{}: {}{}", key, numprocs, comment);
+        }
+        None => lines.insert(
+            at + 1,
+            format!("{}numprocs: {}", " ".repeat(keys), numprocs),
+        ),
+    }
+    let mut out = lines.join("\n");
+    if text.ends_with('\n') {
+        out.push('\n');
+    }
+    Some(out)
+}
+
+// written aside then renamed over, so the config is never left half written
+fn write_aside(path: &str, data: &str) -> io::Result<()> {
+    let tmp = format!("{}.tmp", path);
+    let permissions = fs::metadata(path)?.permissions();
+
+    let _ = fs::remove_file(&tmp);
+    OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(&tmp)
+        .and_then(|mut file| file.write_all(data.as_bytes()))
+        .and_then(|_| fs::set_permissions(&tmp, permissions))
+        .and_then(|_| fs::rename(&tmp, path))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONFIG: &str = "# services\nprograms:\n  - name : web # front\n    command: sleep 100\n    numprocs: 2 # two is enough\n    env:\n      numprocs: x\n  - name: \"worker\"\n    command: sleep 100\n";
+
+    #[test]
+    fn edit_numprocs_keeps_the_rest() {
+        assert_eq!(
+            edit_numprocs(CONFIG, "web", 4).unwrap(),
+            CONFIG.replace("numprocs: 2 #", "numprocs: 4 #")
+        );
+        assert_eq!(
+            edit_numprocs(CONFIG, "worker", 3).unwrap(),
+            CONFIG.replace("\"worker\"\n", "\"worker\"\n    numprocs: 3\n")
+        );
+        assert_eq!(edit_numprocs(CONFIG, "db", 1), None);
+        assert_eq!(
+            edit_numprocs("- numprocs: 1\n  name: web\n", "web", 2).unwrap(),
+            "- numprocs: 2\n  name: web\n"
+        );
+    }
+}
@@ -0,0 +1,241 @@
+use nix::libc;
+use nix::sched::{sched_setaffinity, CpuSet};
+use nix::unistd::Pid;
+use std::ffi::CString;
+use std::fs;
+use std::os::unix::process::CommandExt;
+use yaml_rust::Yaml;
+
+use super::parse::File;
+use super::proc::Proc;
+
+const IOPRIO_WHO_PROCESS: libc::c_long = 1;
+const IOPRIO_CLASS_SHIFT: i32 = 13;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum CpuAffinity {
+    Cpus(Vec<usize>),
+    // instance N of the program runs on CPU N % online CPUs
+    Auto,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Sched {
+    pub nice: Option<i32>,
+    // (class, level), class 1: realtime, 2: best-effort, 3: idle
+    pub ioprio: Option<(i32, i32)>,
+    pub oom_score_adj: Option<i32>,
+    pub cpu_affinity: Option<CpuAffinity>,
+}
+
+fn ioprio_class_from_name(name: &str) -> Option<i32> {
+    match name {
+        "realtime" | "rt" => Some(1),
+        "best-effort" | "be" => Some(2),
+        "idle" => Some(3),
+        _ => None,
+    }
+}
+
+fn ioprio_class_to_name(class: i32) -> &'static str {
+    match class {
+        1 => "realtime",
+        2 => "best-effort",
+        3 => "idle",
+        _ => "none",
+    }
+}
+
+// `[0, 2]`, `"0-3,6"` or `auto`
+fn parse_cpu_affinity(yaml: &Yaml) -> Option<CpuAffinity> {
+    match yaml {
+        Yaml::Integer(cpu) => Some(CpuAffinity::Cpus(vec![*cpu as usize])),
+        Yaml::Array(cpus) => Some(CpuAffinity::Cpus(
+            cpus.iter()
+                .filter_map(|cpu| cpu.as_i64().map(|c| c as usize))
+                .collect(),
+        )),
+        Yaml::String(cpus) if cpus == "auto" => Some(CpuAffinity::Auto),
+        Yaml::String(cpus) => parse_cpu_list(cpus).map(CpuAffinity::Cpus),
+        _ => None,
+    }
+}
+
+fn parse_cpu_list(list: &str) -> Option<Vec<usize>> {
+    let mut cpus = vec![];
+
+    for range in list.split(',').map(str::trim).filter(|r| !r.is_empty()) {
+        match range.split_once('-') {
+            Some((first, last)) => {
+                cpus.extend(first.trim().parse::<usize>().ok()?..=last.trim().parse().ok()?)
+            }
+            None => cpus.push(range.parse().ok()?),
+        }
+    }
+    Some(cpus)
+}
+
+fn online_cpus() -> usize {
+    match unsafe { libc::sysconf(libc::_SC_NPROCESSORS_ONLN) } {
+        n if n > 0 => n as usize,
+        _ => 1,
+    }
+}
+
+impl Sched {
+    pub fn from_yaml(yaml_file: &Yaml) -> Self {
+        let class = yaml_file["ioprio_class"]
+            .as_str()
+            .and_then(ioprio_class_from_name);
+        let level = yaml_file["ioprio"].as_i64().map(|l| l.clamp(0, 7) as i32);
+        let ioprio = match (class, level) {
+            (None, None) => None,
+            // idle has no level
+            (Some(3), _) => Some((3, 0)),
+            (class, level) => Some((class.unwrap_or(2), level.unwrap_or(4))),
+        };
+
+        Sched {
+            nice: yaml_file["nice"].as_i64().map(|n| n.clamp(-20, 19) as i32),
+            ioprio,
+            oom_score_adj: yaml_file["oom_score_adj"]
+                .as_i64()
+                .map(|adj| adj.clamp(-1000, 1000) as i32),
+            cpu_affinity: parse_cpu_affinity(&yaml_file["cpu_affinity"]),
+        }
+    }
+
+    fn cpu_set(&self, instance: i64) -> Option<(CpuSet, Vec<usize>)> {
+        let cpus = match self.cpu_affinity.as_ref()? {
+            CpuAffinity::Cpus(cpus) => cpus.clone(),
+            CpuAffinity::Auto => vec![instance as usize % online_cpus()],
+        };
+        let mut set = CpuSet::new();
+
+        for cpu in &cpus {
+            if set.set(*cpu).is_err() {
+                println!("cpu_affinity: ignoring invalid cpu {}", cpu);
+            }
+        }
+        Some((set, cpus))
+    }
+}
+
+impl Proc {
+    pub fn setup_command_sched(&mut self, parse_file: &File) {
+        let sched = parse_file.sched.clone();
+        let cpu_set = sched.cpu_set(self.instance);
+        let oom_path = CString::new("/proc/self/oom_score_adj").expect("no nul byte");
+        let oom_value = sched.oom_score_adj.map(|adj| adj.to_string());
+        let step = self.spawn_step.clone();
+
+        if sched.nice.is_none()
+            && sched.ioprio.is_none()
+            && oom_value.is_none()
+            && cpu_set.is_none()
+        {
+            return;
+        }
+        let labels = (
+            format!("set nice to {}", sched.nice.unwrap_or(0)),
+            format!("set ioprio to {:?}", sched.ioprio.unwrap_or_default()),
+            format!("set oom_score_adj to {}", sched.oom_score_adj.unwrap_or(0)),
+            format!(
+                "set cpu_affinity to {:?}",
+                cpu_set
+                    .as_ref()
+                    .map(|(_, cpus)| cpus.clone())
+                    .unwrap_or_default()
+            ),
+        );
+
+        if let Some(command) = &mut self.command {
+            unsafe {
+                command.pre_exec(move || {
+                    if let Some(nice) = sched.nice {
+                        step.set(&labels.0);
+                        if libc::setpriority(libc::PRIO_PROCESS, 0, nice) < 0 {
+                            return Err(std::io::Error::last_os_error());
+                        }
+                    }
+                    if let Some((class, level)) = sched.ioprio {
+                        step.set(&labels.1);
+                        let prio = (class << IOPRIO_CLASS_SHIFT) | level;
+                        if libc::syscall(libc::SYS_ioprio_set, IOPRIO_WHO_PROCESS, 0, prio) < 0 {
+                            return Err(std::io::Error::last_os_error());
+                        }
+                    }
+                    if let Some(value) = &oom_value {
+                        step.set(&labels.2);
+                        let fd = libc::open(oom_path.as_ptr(), libc::O_WRONLY | libc::O_CLOEXEC);
+                        if fd < 0 {
+                            return Err(std::io::Error::last_os_error());
+                        }
+                        let res =
+                            libc::write(fd, value.as_ptr() as *const libc::c_void, value.len());
+                        libc::close(fd);
+                        if res != value.len() as isize {
+                            return Err(std::io::Error::last_os_error());
+                        }
+                    }
+                    if let Some((set, _)) = &cpu_set {
+                        step.set(&labels.3);
+                        sched_setaffinity(Pid::from_raw(0), set)?;
+                    }
+                    Ok(())
+                });
+            }
+        }
+    }
+
+    /// nice, ioprio, oom_score_adj and cpu affinity the running process really has.
+    pub fn sched_details(&self) -> Vec<(String, String)> {
+        let pid = match &self.child {
+            Some(child) => child.id() as i32,
+            None => return vec![],
+        };
+        let stat = fs::read_to_string(format!("/proc/{}/stat", pid)).unwrap_or_default();
+        // fields restart after the command name, nice is the 19th field of the line
+        let nice = stat
+            .rfind(')')
+            .and_then(|end| stat[end + 2..].split(' ').nth(16).map(str::to_string));
+        let ioprio = unsafe {
+            libc::syscall(
+                libc::SYS_ioprio_get,
+                IOPRIO_WHO_PROCESS,
+                pid as libc::c_long,
+            )
+        };
+        let ioprio = match ioprio {
+            prio if prio < 0 => "?".to_string(),
+            prio => {
+                let class = (prio >> IOPRIO_CLASS_SHIFT) as i32;
+                match class {
+                    0 => "none".to_string(),
+                    _ => format!(
+                        "{} {}",
+                        ioprio_class_to_name(class),
+                        prio & ((1 << IOPRIO_CLASS_SHIFT) - 1)
+                    ),
+                }
+            }
+        };
+        let oom_score_adj = fs::read_to_string(format!("/proc/{}/oom_score_adj", pid))
+            .map(|adj| adj.trim().to_string());
+        let cpus = fs::read_to_string(format!("/proc/{}/status", pid))
+            .unwrap_or_default()
+            .lines()
+            .find_map(|line| line.strip_prefix("Cpus_allowed_list:"))
+            .map(|cpus| cpus.trim().to_string());
+
+        vec![
+            ("nice".to_string(), nice.unwrap_or("?".to_string())),
+            ("ioprio".to_string(), ioprio),
+            (
+                "oom_score_adj".to_string(),
+                oom_score_adj.unwrap_or("?".to_string()),
+            ),
+            ("cpu_affinity".to_string(), cpus.unwrap_or("?".to_string())),
+        ]
+    }
+}
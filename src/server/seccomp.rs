@@ -0,0 +1,246 @@
+use nix::libc;
+use std::io;
+use std::os::unix::process::CommandExt;
+use yaml_rust::Yaml;
+
+use super::parse::File;
+use super::proc::Proc;
+use super::syscalls::syscall_from_name;
+
+#[cfg(target_arch = "x86_64")]
+const AUDIT_ARCH: u32 = 0xC000_003E;
+#[cfg(target_arch = "aarch64")]
+const AUDIT_ARCH: u32 = 0xC000_00B7;
+// x32 syscalls share the x86_64 arch, they would bypass the numbers below
+const X32_SYSCALL_BIT: u32 = 0x4000_0000;
+
+const NO_DEBUG: &[&str] = &[
+    "ptrace",
+    "process_vm_readv",
+    "process_vm_writev",
+    "perf_event_open",
+    "kcmp",
+];
+
+const NO_ADMIN: &[&str] = &[
+    "mount",
+    "umount2",
+    "pivot_root",
+    "kexec_load",
+    "kexec_file_load",
+    "init_module",
+    "finit_module",
+    "delete_module",
+    "reboot",
+    "swapon",
+    "swapoff",
+    "acct",
+    "settimeofday",
+    "clock_settime",
+    "clock_adjtime",
+    "adjtimex",
+    "sethostname",
+    "setdomainname",
+    "quotactl",
+    "bpf",
+    "open_by_handle_at",
+    "iopl",
+    "ioperm",
+];
+
+const DEFAULT: &[&str] = &[
+    "keyctl",
+    "add_key",
+    "request_key",
+    "userfaultfd",
+    "unshare",
+    "setns",
+];
+
+// the exec of the program itself and its failure path run under the filter
+const ALWAYS_ALLOWED: &[&str] = &["execve", "exit_group"];
+
+fn profile(name: &str) -> Option<Vec<&'static str>> {
+    match name {
+        "no-debug" => Some(NO_DEBUG.to_vec()),
+        "no-admin" => Some(NO_ADMIN.to_vec()),
+        "default" => Some([NO_DEBUG, NO_ADMIN, DEFAULT].concat()),
+        _ => None,
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum SeccompMode {
+    // listed syscalls are refused, everything else is allowed
+    Deny,
+    // listed syscalls are allowed, everything else is refused
+    Allow,
+}
+
+#[derive(Debug, Clone)]
+pub struct Seccomp {
+    pub mode: SeccompMode,
+    pub syscalls: Vec<libc::c_long>,
+    // `action: errno` fails the syscall with EPERM instead of killing with SIGSYS
+    pub errno: bool,
+}
+
+fn syscall_names(yaml: &Yaml) -> Vec<libc::c_long> {
+    let mut syscalls = vec![];
+
+    for name in yaml.as_vec().map(|v| v.as_slice()).unwrap_or_default() {
+        match name.as_str().and_then(syscall_from_name) {
+            Some(nr) => syscalls.push(nr),
+            None => println!("seccomp: ignoring unknown syscall {:?}", name),
+        }
+    }
+    syscalls
+}
+
+impl Seccomp {
+    /// `seccomp: default` or `seccomp: {profile, allow, deny, action}`.
+    pub fn from_yaml(yaml: &Yaml) -> Option<Self> {
+        let profile_name = match yaml {
+            Yaml::String(name) => Some(name.as_str()),
+            Yaml::Hash(_) => yaml["profile"].as_str(),
+            _ => return None,
+        };
+        let profile = match profile_name.map(|name| (name, profile(name))) {
+            Some((_, Some(syscalls))) => syscalls
+                .iter()
+                .filter_map(|name| syscall_from_name(name))
+                .collect(),
+            Some((name, None)) => {
+                println!("seccomp: unknown profile {}, using default", name);
+                profile("default")
+                    .unwrap_or_default()
+                    .iter()
+                    .filter_map(|name| syscall_from_name(name))
+                    .collect()
+            }
+            None => vec![],
+        };
+        let deny = syscall_names(&yaml["deny"]);
+        let errno = yaml["action"].as_str() == Some("errno");
+
+        match yaml["allow"].is_badvalue() {
+            true => Some(Seccomp {
+                mode: SeccompMode::Deny,
+                syscalls: [profile, deny].concat(),
+                errno,
+            }),
+            false => {
+                let mut allow = syscall_names(&yaml["allow"]);
+                allow.extend(ALWAYS_ALLOWED.iter().filter_map(|n| syscall_from_name(n)));
+                allow.retain(|nr| !deny.contains(nr) && !profile.contains(nr));
+                Some(Seccomp {
+                    mode: SeccompMode::Allow,
+                    syscalls: allow,
+                    errno,
+                })
+            }
+        }
+    }
+
+    pub fn filter(&self) -> Vec<libc::sock_filter> {
+        let stmt = |code: u32, k: u32| libc::sock_filter {
+            code: code as u16,
+            jt: 0,
+            jf: 0,
+            k,
+        };
+        let jump = |code: u32, k: u32, jt: u8, jf: u8| libc::sock_filter {
+            code: code as u16,
+            jt,
+            jf,
+            k,
+        };
+        let violation = match self.errno {
+            true => libc::SECCOMP_RET_ERRNO | libc::EPERM as u32,
+            false => libc::SECCOMP_RET_KILL_PROCESS,
+        };
+        let (on_match, default) = match self.mode {
+            SeccompMode::Deny => (violation, libc::SECCOMP_RET_ALLOW),
+            SeccompMode::Allow => (libc::SECCOMP_RET_ALLOW, violation),
+        };
+        let load = libc::BPF_LD | libc::BPF_W | libc::BPF_ABS;
+        let mut filter = vec![
+            // seccomp_data.arch
+            stmt(load, 4),
+            jump(
+                libc::BPF_JMP | libc::BPF_JEQ | libc::BPF_K,
+                AUDIT_ARCH,
+                1,
+                0,
+            ),
+            stmt(libc::BPF_RET | libc::BPF_K, libc::SECCOMP_RET_KILL_PROCESS),
+            // seccomp_data.nr
+            stmt(load, 0),
+        ];
+
+        if cfg!(target_arch = "x86_64") {
+            filter.push(jump(
+                libc::BPF_JMP | libc::BPF_JGE | libc::BPF_K,
+                X32_SYSCALL_BIT,
+                0,
+                1,
+            ));
+            filter.push(stmt(libc::BPF_RET | libc::BPF_K, violation));
+        }
+        for nr in &self.syscalls {
+            filter.push(jump(
+                libc::BPF_JMP | libc::BPF_JEQ | libc::BPF_K,
+                *nr as u32,
+                0,
+                1,
+            ));
+            filter.push(stmt(libc::BPF_RET | libc::BPF_K, on_match));
+        }
+        filter.push(stmt(libc::BPF_RET | libc::BPF_K, default));
+        filter
+    }
+}
+
+/// Load the filter into the calling process, in the child right before exec.
+fn install_filter(filter: &[libc::sock_filter]) -> io::Result<()> {
+    let prog = libc::sock_fprog {
+        len: filter.len() as u16,
+        filter: filter.as_ptr() as *mut libc::sock_filter,
+    };
+    // required to install a filter without CAP_SYS_ADMIN
+    let installed = unsafe {
+        libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0) == 0
+            && libc::prctl(
+                libc::PR_SET_SECCOMP,
+                libc::SECCOMP_MODE_FILTER,
+                &prog as *const libc::sock_fprog,
+            ) == 0
+    };
+
+    match installed {
+        true => Ok(()),
+        false => Err(io::Error::last_os_error()),
+    }
+}
+
+impl Proc {
+    /// Must be the last pre_exec hook: the filter also applies to the hooks
+    /// after it.
+    pub fn setup_command_seccomp(&mut self, parse_file: &File) {
+        let filter = match &parse_file.seccomp {
+            Some(seccomp) => seccomp.filter(),
+            None => return,
+        };
+        self.seccomp = true;
+        let step = self.spawn_step.clone();
+
+        if let Some(command) = &mut self.command {
+            unsafe {
+                command.pre_exec(move || {
+                    step.set("install seccomp filter");
+                    install_filter(&filter)
+                });
+            }
+        }
+    }
+}
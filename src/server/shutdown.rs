@@ -0,0 +1,53 @@
+use chrono::Local;
+
+use super::conf::Taskmaster;
+use share_structures::State;
+
+impl Taskmaster {
+    /// A critical program went FATAL: stop everything and remember the exit code.
+    pub fn check_critical(&mut self) {
+        if self.shutdown_code.is_some() {
+            return;
+        }
+        let critical = self
+            .task_lst
+            .iter()
+            .find(|task| task.parse_file.critical && task.is_fatal());
+
+        if let Some(task) = critical {
+            println!(
+                "{} CRIT {} is critical and entered FATAL state, shutting down",
+                Local::now().format("%d/%m/%Y %H:%M:%S"),
+                task.getnameparse()
+            );
+            self.shutdown_code = Some(self.critical_exitcode);
+        }
+    }
+
+    /// Keep stopping processes until none is left alive, then return the exit code.
+    pub fn shutdown_step(&mut self) -> Option<i32> {
+        let code = self.shutdown_code?;
+        let mut alive = false;
+
+        for task in &mut self.task_lst {
+            task.stop_all_process();
+            alive |= task.process_lst.iter().any(|proc| {
+                proc.state == State::RUNNING
+                    || proc.state == State::STARTING
+                    || proc.state == State::STOPPING
+            });
+        }
+        match alive {
+            true => None,
+            false => Some(code),
+        }
+    }
+
+    /// What dropping everything would do, process::exit runs no destructor:
+    /// notify and listening sockets, private tmp dirs and cgroups are
+    /// removed, the deferred actions saved.
+    pub fn teardown(&mut self) {
+        self.save_deferred();
+        self.task_lst.clear();
+    }
+}
@@ -0,0 +1,450 @@
+use nix::libc;
+use std::env;
+use std::ffi::OsString;
+use std::fs;
+use std::io;
+use std::net::TcpListener;
+use std::os::unix::fs::PermissionsExt;
+use std::os::unix::io::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+use std::os::unix::net::UnixListener;
+use std::os::unix::process::CommandExt;
+use std::path::Path;
+use std::process::{self, Command};
+use std::sync::atomic::{AtomicI32, Ordering};
+use std::sync::{Arc, Mutex};
+use yaml_rust::Yaml;
+
+use super::file::Files;
+use super::parse::File;
+use super::proc::Proc;
+use super::task::Task;
+
+// first fd passed to the child, as in sd_listen_fds
+const LISTEN_FDS_START: RawFd = 3;
+
+/// One `sockets` entry: `tcp://host:port` or `unix:/path`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum SocketSpec {
+    Tcp(String),
+    Unix(String),
+}
+
+impl SocketSpec {
+    pub fn parse(spec: &str) -> Option<Self> {
+        if let Some(addr) = spec.strip_prefix("tcp://") {
+            Some(SocketSpec::Tcp(addr.to_string()))
+        } else {
+            spec.strip_prefix("unix:")
+                .map(|path| SocketSpec::Unix(path.to_string()))
+        }
+    }
+
+    // its LISTEN_FDNAMES entry, which can't hold a ':'
+    fn name(&self) -> String {
+        match self {
+            SocketSpec::Tcp(addr) => format!("tcp-{}", addr.rsplit(':').next().unwrap_or(addr)),
+            SocketSpec::Unix(path) => format!(
+                "unix-{}",
+                Path::new(path)
+                    .file_name()
+                    .map(|name| name.to_string_lossy().to_string())
+                    .unwrap_or_default()
+            ),
+        }
+    }
+}
+
+impl std::fmt::Display for SocketSpec {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SocketSpec::Tcp(addr) => write!(f, "tcp://{}", addr),
+            SocketSpec::Unix(path) => write!(f, "unix:{}", path),
+        }
+    }
+}
+
+pub fn parse_sockets(yaml: &Yaml) -> Vec<SocketSpec> {
+    let specs = match yaml.as_str() {
+        Some(spec) => vec![spec.to_string()],
+        None => yaml
+            .clone()
+            .into_iter()
+            .filter_map(|value| value.as_str().map(|spec| spec.to_string()))
+            .collect(),
+    };
+
+    specs
+        .iter()
+        .filter_map(|spec| match SocketSpec::parse(spec) {
+            Some(spec) => Some(spec),
+            None => {
+                println!("sockets: ignoring {} (expected tcp://host:port or unix:/path)", spec);
+                None
+            }
+        })
+        .collect()
+}
+
+enum Listener {
+    Tcp(TcpListener),
+    Unix(UnixListener, String),
+}
+
+impl Drop for Listener {
+    fn drop(&mut self) {
+        if let Listener::Unix(_, path) = self {
+            let _ = fs::remove_file(path);
+        }
+    }
+}
+
+/// The listening sockets of a program. Bound by the first start and held by
+/// the server from then on, so connections queue while its processes restart.
+pub struct ListenSockets {
+    pub specs: Vec<SocketSpec>,
+    listeners: Mutex<Vec<Listener>>,
+    // bound before a reload that kept them, used instead of binding again
+    kept: Mutex<Vec<(SocketSpec, Listener)>>,
+}
+
+impl ListenSockets {
+    pub fn new(specs: Vec<SocketSpec>) -> Self {
+        ListenSockets {
+            specs,
+            listeners: Mutex::new(vec![]),
+            kept: Mutex::new(vec![]),
+        }
+    }
+
+    /// The sockets of the program after a reload changed them: the ones it
+    /// keeps are taken over, so their address is never released meanwhile.
+    /// The others are closed, processes still running keep their copies.
+    pub fn reloaded(&self, specs: Vec<SocketSpec>) -> Self {
+        let mut listeners = self.listeners.lock().unwrap();
+        let mut kept = std::mem::take(&mut *self.kept.lock().unwrap());
+
+        kept.extend(self.specs.iter().cloned().zip(listeners.drain(..)));
+        kept.retain(|(spec, _)| specs.contains(spec));
+        ListenSockets {
+            specs,
+            listeners: Mutex::new(vec![]),
+            kept: Mutex::new(kept),
+        }
+    }
+
+    fn bind_one(spec: &SocketSpec) -> io::Result<Listener> {
+        match spec {
+            SocketSpec::Tcp(addr) => TcpListener::bind(addr.as_str()).map(Listener::Tcp),
+            SocketSpec::Unix(path) => {
+                // left behind by an earlier run
+                let _ = fs::remove_file(path);
+                UnixListener::bind(path).map(|listener| Listener::Unix(listener, path.clone()))
+            }
+        }
+    }
+
+    // bind every socket if not done yet, return their fds in order
+    fn fds(&self) -> Result<Vec<RawFd>, String> {
+        let mut listeners = self.listeners.lock().unwrap();
+
+        if listeners.is_empty() {
+            let mut kept = self.kept.lock().unwrap();
+
+            for spec in &self.specs {
+                let listener = match kept.iter().position(|(kept, _)| kept == spec) {
+                    Some(index) => kept.remove(index).1,
+                    None => {
+                        Self::bind_one(spec).map_err(|e| format!("can't bind {}: {}", spec, e))?
+                    }
+                };
+                listeners.push(listener);
+            }
+        }
+        Ok(listeners
+            .iter()
+            .map(|listener| match listener {
+                Listener::Tcp(listener) => listener.as_raw_fd(),
+                Listener::Unix(listener, _) => listener.as_raw_fd(),
+            })
+            .collect())
+    }
+}
+
+/// argv[1] of the server when it runs as the first step of a process with
+/// sockets, see listen_exec.
+pub const LISTEN_EXEC_ARG: &str = "--listen-exec";
+const HANDOFF_VAR: &str = "TASKMASTER_LISTEN_FDS";
+
+/// The command of a new process of `program`. With sockets, it goes through
+/// the server binary, which passes them and execs the program.
+fn program_command(program: &str, sockets: &ListenSockets) -> Command {
+    match sockets.specs.is_empty() {
+        true => Command::new(program),
+        false => {
+            let mut command = Command::new("/proc/self/exe");
+            command.args([LISTEN_EXEC_ARG, program]);
+            command
+        }
+    }
+}
+
+impl Task {
+    pub fn new_command(&self) -> Command {
+        program_command(self.getpathref(), &self.sockets)
+    }
+
+    /// On reload: new sockets if `sockets` changed. The processes are set up
+    /// for them now if down, else once they are (see setup_again).
+    pub fn reload_sockets(&mut self) {
+        if self.sockets.specs == self.parse_file.sockets {
+            return;
+        }
+        self.sockets = Arc::new(self.sockets.reloaded(self.parse_file.sockets.clone()));
+        for proc in &mut self.process_lst {
+            proc.stale_sockets = true;
+            if proc.child.is_none() {
+                proc.setup_again(&self.parse_file, &self.sockets);
+            }
+        }
+    }
+}
+
+/// `server --listen-exec <program> [args]`: std did the fork and the exec
+/// into here and reported whatever failed until then. Move the copies of the
+/// listening sockets to 3.. and exec the program with LISTEN_PID set.
+pub fn listen_exec(args: &[OsString]) -> ! {
+    let fds: Vec<RawFd> = env::var(HANDOFF_VAR)
+        .unwrap_or_default()
+        .split_whitespace()
+        .filter_map(|fd| fd.parse().ok())
+        .collect();
+    env::remove_var(HANDOFF_VAR);
+
+    // the copies are all above 3..3+n, none is overwritten by another
+    for (i, fd) in fds.iter().enumerate() {
+        // dup2 leaves FD_CLOEXEC off on the copy
+        if unsafe { libc::dup2(*fd, LISTEN_FDS_START + i as RawFd) } < 0 {
+            eprintln!("taskmaster: can't pass fd {}: {}", fd, io::Error::last_os_error());
+            process::exit(127);
+        }
+        unsafe { libc::close(*fd) };
+    }
+    env::set_var("LISTEN_PID", process::id().to_string());
+
+    let e = match args.split_first() {
+        Some((program, args)) => Command::new(program).args(args).exec(),
+        None => io::Error::from_raw_os_error(libc::ENOENT),
+    };
+    eprintln!("taskmaster: can't exec {:?}: {}", args.first().unwrap_or(&OsString::new()), e);
+    process::exit(127);
+}
+
+// can execvp run it: past the fork, listen_exec could only report it by exiting
+fn find_program(program: &str, dir: Option<&Path>, path: Option<OsString>) -> io::Result<()> {
+    let runnable = |file: &Path| {
+        fs::metadata(file)
+            .map(|meta| meta.is_file() && meta.permissions().mode() & 0o111 != 0)
+            .unwrap_or(false)
+    };
+    let found = match program.contains('/') {
+        true => runnable(&dir.unwrap_or(Path::new("")).join(program)),
+        false => env::split_paths(&path.unwrap_or_else(|| "/usr/bin:/bin".into()))
+            .any(|dir| runnable(&dir.join(program))),
+    };
+
+    match found {
+        true => Ok(()),
+        false => Err(io::Error::from_raw_os_error(libc::ENOENT)),
+    }
+}
+
+/// Socket activation of one process.
+pub struct ListenExec {
+    sockets: Arc<ListenSockets>,
+    program: String,
+    // this spawn's copies of the listening fds: CLOEXEC in the server, the
+    // child they are made for clears it so they survive into listen_exec
+    handoff: Vec<AtomicI32>,
+    held: Mutex<Vec<OwnedFd>>,
+}
+
+impl Proc {
+    pub fn setup_command_sockets(&mut self, sockets: &Arc<ListenSockets>, parse_file: &File) {
+        if sockets.specs.is_empty() {
+            return;
+        }
+        let listen = Arc::new(ListenExec {
+            sockets: sockets.clone(),
+            program: parse_file.path_command.clone().unwrap_or_default(),
+            handoff: sockets.specs.iter().map(|_| AtomicI32::new(-1)).collect(),
+            held: Mutex::new(vec![]),
+        });
+        let exec = listen.clone();
+        let step = self.spawn_step.clone();
+
+        if let Some(command) = &mut self.command {
+            command.env("LISTEN_FDS", sockets.specs.len().to_string());
+            command.env(
+                "LISTEN_FDNAMES",
+                sockets
+                    .specs
+                    .iter()
+                    .map(|spec| spec.name())
+                    .collect::<Vec<String>>()
+                    .join(":"),
+            );
+            command.env_remove("LISTEN_PID");
+            unsafe {
+                command.pre_exec(move || {
+                    step.set("pass listening sockets");
+                    for fd in &exec.handoff {
+                        if libc::fcntl(fd.load(Ordering::Relaxed), libc::F_SETFD, 0) < 0 {
+                            return Err(io::Error::last_os_error());
+                        }
+                    }
+                    Ok(())
+                });
+            }
+        }
+        self.listen = Some(listen);
+    }
+
+    /// Bind the sockets if not done yet and make the copies of this spawn.
+    pub fn prepare_listen_fds(&mut self) -> Result<(), String> {
+        let listen = match &self.listen {
+            Some(listen) => listen.clone(),
+            None => return Ok(()),
+        };
+        let command = self.command.as_mut().expect("Start");
+        let path = command
+            .get_envs()
+            .find(|(key, _)| *key == "PATH")
+            .map(|(_, value)| value.map(|value| value.to_os_string()))
+            .unwrap_or_else(|| env::var_os("PATH"));
+        find_program(&listen.program, command.get_current_dir(), path)
+            .map_err(|e| format!("{e}"))?;
+
+        let fds = listen.sockets.fds()?;
+        let mut held = listen.held.lock().unwrap();
+        held.clear();
+        for ((fd, spec), slot) in fds.iter().zip(&listen.sockets.specs).zip(&listen.handoff) {
+            // above 3..3+n, listen_exec never overwrites one while moving another
+            let copy = unsafe {
+                libc::fcntl(
+                    *fd,
+                    libc::F_DUPFD_CLOEXEC,
+                    LISTEN_FDS_START + fds.len() as RawFd,
+                )
+            };
+            if copy < 0 {
+                return Err(format!("can't pass {}: {}", spec, io::Error::last_os_error()));
+            }
+            let copy = unsafe { OwnedFd::from_raw_fd(copy) };
+            slot.store(copy.as_raw_fd(), Ordering::Relaxed);
+            held.push(copy);
+        }
+        command.env(
+            HANDOFF_VAR,
+            held.iter()
+                .map(|fd| fd.as_raw_fd().to_string())
+                .collect::<Vec<String>>()
+                .join(" "),
+        );
+        Ok(())
+    }
+
+    // the child has its own copies by now
+    /// A new command and setup for the sockets of the program after a reload.
+    /// Only while down: the resources of the old setup are released first.
+    pub fn setup_again(&mut self, parse_file: &File, sockets: &Arc<ListenSockets>) {
+        // freed before the new setup takes their paths again
+        self.notify = None;
+        self.watchdog = None;
+        self.remove_private_tmp();
+        self.private_tmp = None;
+        self.cgroup = None;
+        self.listen = None;
+        self.seccomp = false;
+        self.stale_sockets = false;
+
+        self.command = Some(program_command(
+            parse_file.path_command.as_deref().unwrap_or_default(),
+            sockets,
+        ));
+        self.redirection();
+        self.setup(parse_file, sockets, self.instance);
+    }
+
+    pub fn release_listen_fds(&mut self) {
+        if let Some(listen) = &self.listen {
+            listen.held.lock().unwrap().clear();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use yaml_rust::YamlLoader;
+
+    fn task(command: &str) -> Task {
+        let config = format!(
+            "name: sock\ncommand: {}\nstdout: /dev/null\nstderr: /dev/null\nsockets: tcp://127.0.0.1:0\n",
+            command
+        );
+        let mut task = Task::new(&YamlLoader::load_from_str(&config).unwrap()[0]);
+        task.parse_file.init_args();
+        task.set_command("sock".to_string());
+        task
+    }
+
+    #[test]
+    fn find_program_like_execvp() {
+        assert!(find_program("/bin/sh", None, None).is_ok());
+        assert!(find_program("sh", None, Some("/nonexistent:/bin".into())).is_ok());
+        assert!(find_program("sh", None, Some("/nonexistent".into())).is_err());
+        assert!(find_program("bin/sh", Some(Path::new("/")), None).is_ok());
+        // not a file
+        assert!(find_program("/", None, None).is_err());
+        assert!(find_program("/nonexistent/prog", None, None).is_err());
+    }
+
+    #[test]
+    fn bad_command_is_a_spawn_error() {
+        let mut task = task("/nonexistent/prog");
+        let proc = &mut task.process_lst[0];
+
+        assert_eq!(
+            proc.spawn_child().err(),
+            Some("No such file or directory (os error 2)".to_string())
+        );
+        // nothing bound for a process that can't start
+        assert!(task.sockets.listeners.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn reload_takes_over_kept_sockets() {
+        let path = env::temp_dir().join(format!("taskmaster-test-{}.sock", process::id()));
+        let unix = SocketSpec::Unix(path.to_string_lossy().to_string());
+        let tcp = SocketSpec::Tcp("127.0.0.1:0".to_string());
+        let old = ListenSockets::new(vec![unix, tcp.clone()]);
+        let fds = old.fds().unwrap();
+
+        let new = old.reloaded(vec![tcp]);
+        // not bound again: a new port would have been picked
+        assert_eq!(new.fds().unwrap(), vec![fds[1]]);
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn reload_sets_up_stopped_processes_again() {
+        let mut task = task("/bin/true");
+        assert_eq!(task.process_lst[0].command.as_ref().unwrap().get_program(), "/proc/self/exe");
+
+        task.parse_file.sockets = vec![];
+        task.reload_sockets();
+        let proc = &task.process_lst[0];
+        assert_eq!(proc.command.as_ref().unwrap().get_program(), "/bin/true");
+        assert!(proc.listen.is_none() && !proc.stale_sockets);
+    }
+}
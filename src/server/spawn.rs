@@ -0,0 +1,96 @@
+use nix::libc;
+use std::process::Child;
+use std::time::Instant;
+use std::{io, ptr};
+
+use super::proc::Proc;
+
+const STEP_LEN: usize = 256;
+
+/// Page shared with the forked child. The pre_exec hooks write the step they
+/// are running into it, so a failed spawn can say which step failed instead
+/// of only carrying the errno.
+pub struct SpawnStep {
+    page: *mut u8,
+}
+
+// the page is only written by the forked child and read after spawn returned
+unsafe impl Send for SpawnStep {}
+unsafe impl Sync for SpawnStep {}
+
+impl SpawnStep {
+    pub fn new() -> io::Result<Self> {
+        let page = unsafe {
+            libc::mmap(
+                ptr::null_mut(),
+                STEP_LEN,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_SHARED | libc::MAP_ANONYMOUS,
+                -1,
+                0,
+            )
+        };
+        if page == libc::MAP_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(SpawnStep {
+            page: page as *mut u8,
+        })
+    }
+
+    // async-signal-safe: no allocation, plain copy
+    pub fn set(&self, step: &str) {
+        let len = step.len().min(STEP_LEN - 1);
+
+        unsafe {
+            ptr::copy_nonoverlapping(step.as_ptr(), self.page, len);
+            *self.page.add(len) = 0;
+        }
+    }
+
+    pub fn get(&self) -> String {
+        let bytes = unsafe { std::slice::from_raw_parts(self.page, STEP_LEN) };
+        let len = bytes.iter().position(|b| *b == 0).unwrap_or(STEP_LEN);
+
+        String::from_utf8_lossy(&bytes[..len]).to_string()
+    }
+}
+
+impl Drop for SpawnStep {
+    fn drop(&mut self) {
+        unsafe {
+            libc::munmap(self.page as *mut libc::c_void, STEP_LEN);
+        }
+    }
+}
+
+impl Proc {
+    pub fn spawn_child(&mut self) -> Result<Child, String> {
+        self.prepare_cgroup()?;
+        self.prepare_private_tmp()?;
+        self.prepare_landlock();
+        self.prepare_listen_fds()?;
+        self.spawn_step.set("");
+        // a new child may get the pid of the one reaped before
+        self.reaped = None;
+        let spawned = self.command.as_mut().expect("Start").spawn();
+        self.release_listen_fds();
+        match spawned {
+            Ok(child) => {
+                self.spawned_time = Some(Instant::now());
+                Ok(child)
+            }
+            Err(e) => match self.spawn_step.get() {
+                step if step.is_empty() => Err(format!("{e}")),
+                step => Err(format!("can't {}: {e}", step)),
+            },
+        }
+    }
+
+    pub fn spawn_failed(&mut self, error: String) {
+        println!("{}", self.spawnerr_log(&error));
+        self.description = format!("spawnerr: {}", error);
+        self.remove_private_tmp();
+        self.change_to_fatal();
+    }
+}
@@ -1,13 +1,20 @@
-use std::{fmt, time::Duration};
+use share_structures::State;
+use std::{fmt, sync::Arc, time::Duration};
 use yaml_rust::Yaml;
 
 use crate::loop_exec::exec_loop;
 
 use super::{
     file::{FileLog, Files},
+    memory::MemoryWatch,
     parse::File,
     proc::Proc,
+    replace::Replacement,
+    rolling::RollingRestart,
+    socket::ListenSockets,
     tool::n_name,
+    uptime::MaxUptime,
+    watch::FileWatch,
 };
 
 pub struct Task {
@@ -18,6 +25,11 @@ pub struct Task {
     pub num_restart: i64,
     pub loop_test: Option<fn(&Task) -> bool>, //possible mais theoriquement nul car 2 proc peuvent ne pas avoir le meme status
     pub is_active: bool,
+    pub fallback_started: bool,
+    pub rolling: Option<RollingRestart>,
+    pub replacing: Option<Replacement>,
+    // held for the life of the program, shared by its processes
+    pub sockets: Arc<ListenSockets>,
 }
 
 impl fmt::Debug for Task {
@@ -35,14 +47,20 @@ impl fmt::Debug for Task {
 impl Task {
     // ______________________ Actions _________________________
     pub fn new(task_config: &Yaml) -> Self {
+        let parse_file = File::from_yaml(task_config);
+
         Task {
             name: String::new(),
             process_lst: Vec::new(),
-            parse_file: File::from_yaml(task_config),
+            sockets: Arc::new(ListenSockets::new(parse_file.sockets.clone())),
+            parse_file,
             parse_doc_yaml: task_config.clone(),
             num_restart: 0,
             loop_test: None,
             is_active: true,
+            fallback_started: false,
+            rolling: None,
+            replacing: None,
             // file_log: Some(FileLog::from_file(task_file, task_name))
         }
     }
@@ -59,14 +77,7 @@ impl Task {
                 true => {
                     self.parse_file.set_conf_default();
                     new_proc = self.proc_file_log(n_name(name.clone(), i), &i, &mut filelog);
-                    new_proc.set_args_task(&self.parse_file);
-                    new_proc.setup_command_umask(&self.parse_file);
-                    new_proc.exp_exit = self.parse_file.exitcodes.clone();
-                    new_proc.stopsignal = self.parse_file.stopsignal;
-                    new_proc.exp_duration = Duration::new(
-                        self.parse_file.starttime.unwrap_or(0).try_into().unwrap_or(0),
-                        0,
-                    );
+                    self.setup_proc(&mut new_proc, i);
                     self.process_lst.push(new_proc);
                 }
                 false => {}
@@ -75,6 +86,11 @@ impl Task {
         numprocs
     }
 
+    // apply the program config to a freshly created process
+    pub fn setup_proc(&self, new_proc: &mut Proc, instance: i64) {
+        new_proc.setup(&self.parse_file, &self.sockets, instance);
+    }
+
     pub fn proc_file_log(&self, n_name: String, i: &i64, filelog: &mut FileLog) -> Proc {
         let mut new_proc: Proc;
 
@@ -114,11 +130,12 @@ impl Task {
         self.parse_file.numprocs.unwrap()
     }
 
-    #[allow(unused_must_use)]
     pub fn start_all_proc(&mut self) {
         // need to depend of args numprocs_start and autostart
         for i in &mut self.process_lst {
-            i.start();
+            if i.state == State::STOPPED || i.state == State::EXITED {
+                i.enqueue();
+            }
         }
     }
 
@@ -139,18 +156,16 @@ impl Task {
         }
     }
 
-    #[allow(unused_must_use)]
-    pub fn remove_nb_process(&mut self, mut to_be_remove: i64) {
-        let mut last = self.process_lst.len() as i32 - 1;
-
-        if to_be_remove > 0 {
-            while to_be_remove > 0 {
-                self.process_lst[last as usize].stop();
-                self.process_lst[last as usize].name = None;
-                to_be_remove -= 1;
-                last -= 1;
-                self.process_lst.pop();
+    // stop the highest-numbered instances, they are removed once down
+    pub fn remove_nb_process(&mut self, to_be_remove: i64) {
+        let mut to_be_remove = to_be_remove;
+
+        for proc in self.process_lst.iter_mut().rev().filter(|proc| !proc.retiring) {
+            if to_be_remove <= 0 {
+                break;
             }
+            proc.retire();
+            to_be_remove -= 1;
         }
     }
 
@@ -160,19 +175,58 @@ impl Task {
     }
 
     pub fn add_nb_process(&mut self, mut to_be_add: i64) {
+        to_be_add -= self.revive_nb_process(to_be_add);
         while to_be_add > 0 {
             let index = self.process_lst.len() as i64;
             let name = n_name(self.getnameparse().clone(), index);
             let mut log_file = FileLog::from_file(self.parse_doc_yaml.clone(), Some(name.clone()));
             let mut new_proc = self.proc_file_log(name, &index, &mut log_file);
 
-            new_proc.set_args_task(&self.parse_file);
-            new_proc.setup_command_umask(&self.parse_file);
-            new_proc.stopsignal = self.parse_file.stopsignal;
-            new_proc.exp_exit = self.parse_file.exitcodes.clone();
+            self.setup_proc(&mut new_proc, index);
             self.process_lst.push(new_proc);
 
             to_be_add -= 1;
         }
     }
 }
+
+impl Proc {
+    // the program config, and its sockets, applied to the process
+    pub fn setup(&mut self, parse_file: &File, sockets: &Arc<ListenSockets>, instance: i64) {
+        self.instance = instance;
+        self.setup_command_cgroup(parse_file, parse_file.name.as_ref().expect("no name ?"));
+        self.set_args_task(parse_file);
+        self.setup_command_umask(parse_file);
+        self.setup_command_rlimits(parse_file);
+        self.setup_command_sched(parse_file);
+        self.setup_command_private_tmp(parse_file);
+        self.setup_command_isolate(parse_file);
+        self.setup_command_privileges(parse_file);
+        self.setup_command_landlock(parse_file);
+        self.setup_command_sockets(sockets, parse_file);
+        self.setup_command_seccomp(parse_file);
+        self.setup_command_notify(parse_file);
+        self.start_conditions = parse_file.start_conditions.clone();
+        self.memory_watch = MemoryWatch::from_file(parse_file);
+        self.setup_schedule(parse_file);
+        self.file_watch = FileWatch::from_file(parse_file, &self.get_name());
+        self.max_uptime = MaxUptime::from_file(parse_file);
+        self.oneshot = parse_file.oneshot;
+        self.timeout = parse_file
+            .timeout
+            .map(|secs| Duration::from_secs(secs.max(0) as u64));
+        self.exp_exit = parse_file.exitcodes.clone();
+        self.stopsignal = parse_file.stopsignal;
+        self.start_stagger = Duration::from_secs(
+            parse_file
+                .start_stagger
+                .unwrap_or(0)
+                .try_into()
+                .unwrap_or(0),
+        );
+        self.start_splay =
+            Duration::from_secs(parse_file.start_splay.unwrap_or(0).try_into().unwrap_or(0));
+        self.exp_duration =
+            Duration::new(parse_file.starttime.unwrap_or(0).try_into().unwrap_or(0), 0);
+    }
+}
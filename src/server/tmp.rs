@@ -0,0 +1,155 @@
+use nix::libc;
+use nix::unistd::{chown, Gid, Uid};
+use std::cell::UnsafeCell;
+use std::ffi::OsStr;
+use std::fs;
+use std::io;
+use std::os::unix::ffi::{OsStrExt, OsStringExt};
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use super::parse::File;
+use super::privilege::user_owner;
+use super::proc::Proc;
+use super::runtime::private_dir;
+
+const PATH_LEN: usize = 4096;
+
+/// Path of the private tmp of the next run for the isolate hook: written
+/// before the fork, read in the child without locking or allocating.
+pub struct TmpSource {
+    path: UnsafeCell<[u8; PATH_LEN]>,
+}
+
+// only written by the thread about to spawn, under the taskmaster lock
+unsafe impl Send for TmpSource {}
+unsafe impl Sync for TmpSource {}
+
+impl TmpSource {
+    fn set(&self, path: &[u8]) {
+        let len = path.len().min(PATH_LEN - 1);
+
+        unsafe {
+            let buf = &mut *self.path.get();
+            buf[..len].copy_from_slice(&path[..len]);
+            buf[len] = 0;
+        }
+    }
+
+    pub fn as_ptr(&self) -> *const libc::c_char {
+        self.path.get() as *const libc::c_char
+    }
+}
+
+pub struct PrivateTmp {
+    // the directory of the current run, a new one for each start
+    pub path: Option<PathBuf>,
+    pub owner: Option<(Uid, Gid)>,
+    // bind-mounted on /tmp (see isolate.rs) rather than given through TMPDIR
+    pub on_tmp: bool,
+    pub source: Arc<TmpSource>,
+}
+
+impl std::fmt::Debug for PrivateTmp {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("PrivateTmp")
+            .field("path", &self.path)
+            .field("owner", &self.owner)
+            .finish()
+    }
+}
+
+impl PrivateTmp {
+    /// A fresh directory for the next run, made by mkdtemp under the
+    /// daemon's private runtime dir.
+    pub fn prepare(&mut self, proc_name: &str) -> Result<PathBuf, String> {
+        self.remove();
+        let base = private_dir("private-tmp")?;
+        let mut template = base
+            .join(format!("{}.XXXXXX", proc_name))
+            .into_os_string()
+            .into_vec();
+        template.push(0);
+
+        if unsafe { libc::mkdtemp(template.as_mut_ptr() as *mut libc::c_char) }.is_null() {
+            return Err(format!(
+                "can't create private tmp in {}: {}",
+                base.display(),
+                io::Error::last_os_error()
+            ));
+        }
+        template.pop();
+        let path = PathBuf::from(OsStr::from_bytes(&template));
+        self.path = Some(path.clone());
+        if let Some((uid, gid)) = self.owner {
+            chown(&path, Some(uid), Some(gid))
+                .map_err(|e| format!("can't chown private tmp {}: {e}", path.display()))?;
+        }
+        self.source.set(&template);
+        Ok(path)
+    }
+
+    pub fn remove(&mut self) {
+        // std walks it with openat and O_NOFOLLOW: links the program left in
+        // there are removed, never followed
+        if let Some(path) = self.path.take() {
+            let _ = fs::remove_dir_all(path);
+        }
+    }
+}
+
+// also on shutdown, with the process dropped
+impl Drop for PrivateTmp {
+    fn drop(&mut self) {
+        self.remove();
+    }
+}
+
+impl Proc {
+    /// With a mount namespace the directory is bind-mounted on /tmp (see isolate.rs).
+    pub fn setup_command_private_tmp(&mut self, parse_file: &File) {
+        if !parse_file.private_tmp {
+            return;
+        }
+        let on_tmp = parse_file
+            .isolate
+            .as_ref()
+            .map(|isolate| isolate.has(libc::CLONE_NEWNS))
+            == Some(true);
+
+        if on_tmp {
+            if let Some(command) = &mut self.command {
+                command.env("TMPDIR", "/tmp");
+            }
+        }
+        self.private_tmp = Some(PrivateTmp {
+            path: None,
+            owner: user_owner(parse_file),
+            on_tmp,
+            source: Arc::new(TmpSource {
+                path: UnsafeCell::new([0; PATH_LEN]),
+            }),
+        });
+    }
+
+    pub fn prepare_private_tmp(&mut self) -> Result<(), String> {
+        let name = self.get_name();
+        let (path, on_tmp) = match &mut self.private_tmp {
+            Some(tmp) => (tmp.prepare(&name)?, tmp.on_tmp),
+            None => return Ok(()),
+        };
+
+        if !on_tmp {
+            if let Some(command) = &mut self.command {
+                command.env("TMPDIR", &path);
+            }
+        }
+        Ok(())
+    }
+
+    pub fn remove_private_tmp(&mut self) {
+        if let Some(tmp) = &mut self.private_tmp {
+            tmp.remove();
+        }
+    }
+}
@@ -1,14 +1,16 @@
+use std::collections::hash_map::RandomState;
 use std::fs;
+use std::hash::{BuildHasher, Hasher};
+use std::time::Duration;
 
 use super::parse::File;
 use yaml_rust::{Yaml, YamlLoader};
 
-
 pub trait DurationDate {
     fn durationdate(&self) -> String;
 }
 
-impl DurationDate for std::time::Duration {
+impl DurationDate for Duration {
     fn durationdate(&self) -> String {
         let secs = self.as_secs() % 60;
         let minutes = (self.as_secs() / 60) % 60;
@@ -18,6 +20,15 @@ impl DurationDate for std::time::Duration {
     }
 }
 
+// uniform random duration in [0, max), seeded by std's per-process random hasher keys
+pub fn random_duration(max: Duration) -> Duration {
+    if max.is_zero() {
+        return Duration::ZERO;
+    }
+    let random = RandomState::new().build_hasher().finish();
+    Duration::from_millis(random % max.as_millis() as u64)
+}
+
 pub fn n_name(name: String, nb_iter: i64) -> String {
     let n_name = name + &"_".to_string() + &nb_iter.to_string();
     n_name
@@ -47,4 +58,4 @@ pub fn file_to_yaml(path: &str) -> Yaml {
     let strfile: String = fs::read_to_string(path).unwrap();
     let docs = YamlLoader::load_from_str(&strfile).unwrap();
     docs[0].clone()
-}
\ No newline at end of file
+}
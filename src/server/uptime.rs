@@ -0,0 +1,94 @@
+use chrono::Local;
+use std::time::Duration;
+
+use super::conf::Taskmaster;
+use super::parse::File;
+use super::proc::Proc;
+use super::task::Task;
+use super::tool::{random_duration, DurationDate};
+use share_structures::State;
+
+/// `max_uptime` and `max_uptime_jitter` of a program.
+#[derive(Debug, Clone, Copy)]
+pub struct MaxUptime {
+    pub max: Duration,
+    pub jitter: Duration,
+}
+
+impl MaxUptime {
+    pub fn from_file(parse_file: &File) -> Option<Self> {
+        let secs = |secs: Option<i64>| Duration::from_secs(secs.unwrap_or(0).max(0) as u64);
+
+        match parse_file.max_uptime {
+            Some(max) if max > 0 => Some(MaxUptime {
+                max: secs(Some(max)),
+                jitter: secs(parse_file.max_uptime_jitter),
+            }),
+            _ => None,
+        }
+    }
+}
+
+impl Proc {
+    /// Draw the uptime this run is recycled at, on each entry in RUNNING.
+    pub fn arm_max_uptime(&mut self) {
+        self.uptime_limit = self
+            .max_uptime
+            .map(|limit| limit.max + random_duration(limit.jitter));
+    }
+
+    // how long the process has been up past its limit
+    fn uptime_overdue(&self) -> Option<Duration> {
+        if self.state != State::RUNNING {
+            return None;
+        }
+        let uptime = self.started_time?.elapsed();
+
+        uptime.checked_sub(self.uptime_limit?)
+    }
+}
+
+impl Task {
+    // an instance is already on its way down or back up
+    fn is_recycling(&self) -> bool {
+        self.process_lst.iter().any(|proc| {
+            proc.restart_on_stop
+                || matches!(
+                    proc.state,
+                    State::STOPPING | State::STARTING | State::BACKOFF | State::QUEUED
+                )
+        })
+    }
+
+    /// Gracefully restart the process most past its max_uptime, one instance
+    /// of the program at a time.
+    pub fn check_max_uptime(&mut self) {
+        if self.is_recycling() {
+            return;
+        }
+        let oldest = self
+            .process_lst
+            .iter_mut()
+            .filter_map(|proc| proc.uptime_overdue().map(|overdue| (overdue, proc)))
+            .max_by_key(|(overdue, _)| *overdue);
+
+        if let Some((_, proc)) = oldest {
+            println!(
+                "{} INFO max_uptime: {} up for {}, recycling",
+                Local::now().format("%d/%m/%Y %H:%M:%S"),
+                proc.get_name(),
+                proc.started_time.unwrap().elapsed().durationdate()
+            );
+            proc.restart_on_stop = true;
+            let _ = proc.stop();
+        }
+    }
+}
+
+impl Taskmaster {
+    pub fn recycle_processes(&mut self) {
+        for task in &mut self.task_lst {
+            task.check_max_uptime();
+        }
+    }
+}
@@ -0,0 +1,282 @@
+use chrono::Local;
+use nix::sys::inotify::{AddWatchFlags, InitFlags, Inotify, WatchDescriptor};
+use nix::sys::signal::{kill, Signal};
+use nix::unistd::{close, Pid};
+use std::fs;
+use std::os::unix::io::AsRawFd;
+use std::path::{Component, Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use super::parse::File;
+use super::proc::Proc;
+use share_structures::State;
+
+/// inotify watches of `watch`, the program is restarted (or signaled) once
+/// the watched files stop changing for `debounce`.
+pub struct FileWatch {
+    inotify: Inotify,
+    // watched directory, file names to look for in it (None: anything)
+    dirs: Vec<(WatchDescriptor, Option<String>)>,
+    debounce: Duration,
+    signal: Option<Signal>,
+    changed: Option<(Instant, PathBuf)>,
+}
+
+impl Drop for FileWatch {
+    fn drop(&mut self) {
+        let _ = close(self.inotify.as_raw_fd());
+    }
+}
+
+fn has_glob(pattern: &str) -> bool {
+    pattern.contains(['*', '?', '['])
+}
+
+// `*`, `?` and `[abc]` / `[a-z]` / `[!abc]`, inside a single path component
+pub fn glob_match(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+
+    fn class(pattern: &[char], c: char) -> Option<(bool, usize)> {
+        let end = pattern.iter().skip(1).position(|p| *p == ']')? + 1;
+        let (negated, set) = match pattern.get(1) {
+            Some('!') | Some('^') => (true, &pattern[2..end]),
+            _ => (false, &pattern[1..end]),
+        };
+        let mut found = false;
+        let mut i = 0;
+        while i < set.len() {
+            if i + 2 < set.len() && set[i + 1] == '-' {
+                found |= set[i] <= c && c <= set[i + 2];
+                i += 3;
+            } else {
+                found |= set[i] == c;
+                i += 1;
+            }
+        }
+        Some((found != negated, end + 1))
+    }
+    fn matches(pattern: &[char], name: &[char]) -> bool {
+        match (pattern.first(), name.first()) {
+            (None, None) => true,
+            (Some('*'), _) => {
+                matches(&pattern[1..], name) || (!name.is_empty() && matches(pattern, &name[1..]))
+            }
+            (Some('?'), Some(_)) => matches(&pattern[1..], &name[1..]),
+            (Some('['), Some(c)) => match class(pattern, *c) {
+                Some((true, len)) => matches(&pattern[len..], &name[1..]),
+                Some((false, _)) => false,
+                None => *c == '[' && matches(&pattern[1..], &name[1..]),
+            },
+            (Some(p), Some(c)) => p == c && matches(&pattern[1..], &name[1..]),
+            _ => false,
+        }
+    }
+    matches(&pattern, &name)
+}
+
+// directories matching `pattern`, globs allowed in any component
+fn expand_dirs(pattern: &Path) -> Vec<PathBuf> {
+    let mut dirs = vec![PathBuf::new()];
+
+    for component in pattern.components() {
+        let part = component.as_os_str().to_string_lossy();
+        dirs = match component {
+            Component::Normal(_) if has_glob(&part) => dirs
+                .iter()
+                .flat_map(|dir| {
+                    let base = match dir.as_os_str().is_empty() {
+                        true => Path::new("."),
+                        false => dir.as_path(),
+                    };
+                    fs::read_dir(base)
+                        .map(|entries| entries.filter_map(|e| e.ok()).collect::<Vec<_>>())
+                        .unwrap_or_default()
+                        .into_iter()
+                        .filter(|e| glob_match(&part, &e.file_name().to_string_lossy()))
+                        .map(|e| dir.join(e.file_name()))
+                        .collect::<Vec<_>>()
+                })
+                .collect(),
+            _ => dirs.iter().map(|dir| dir.join(component)).collect(),
+        };
+    }
+    dirs.into_iter().filter(|dir| dir.is_dir()).collect()
+}
+
+impl FileWatch {
+    pub fn from_file(parse_file: &File, proc_name: &str) -> Option<Self> {
+        if parse_file.watch.is_empty() {
+            return None;
+        }
+        let inotify = match Inotify::init(InitFlags::IN_NONBLOCK | InitFlags::IN_CLOEXEC) {
+            Ok(inotify) => inotify,
+            Err(e) => {
+                println!(
+                    "{} WARN watch: can't watch files for {}: {}",
+                    Local::now().format("%d/%m/%Y %H:%M:%S"),
+                    proc_name,
+                    e
+                );
+                return None;
+            }
+        };
+        let mut watch = FileWatch {
+            inotify,
+            dirs: vec![],
+            debounce: Duration::from_secs(
+                parse_file
+                    .watch_debounce
+                    .unwrap_or(1)
+                    .try_into()
+                    .unwrap_or(0),
+            ),
+            signal: parse_file.watch_signal,
+            changed: None,
+        };
+        let flags = AddWatchFlags::IN_CLOSE_WRITE
+            | AddWatchFlags::IN_MOVED_TO
+            | AddWatchFlags::IN_MOVED_FROM
+            | AddWatchFlags::IN_CREATE
+            | AddWatchFlags::IN_DELETE
+            | AddWatchFlags::IN_ATTRIB;
+
+        for pattern in &parse_file.watch {
+            let path = Path::new(pattern);
+            // a directory is watched as a whole, a file through its directory
+            // so that a file replaced by a rename is still seen
+            let name = path
+                .file_name()
+                .map(|name| name.to_string_lossy().to_string());
+            let dirs: Vec<(PathBuf, Option<String>)> = expand_dirs(path)
+                .into_iter()
+                .map(|dir| (dir, None))
+                .chain(
+                    expand_dirs(path.parent().unwrap_or(Path::new("")))
+                        .into_iter()
+                        .map(|dir| (dir, name.clone())),
+                )
+                .collect();
+            if dirs.is_empty() {
+                println!(
+                    "{} WARN watch: {} has nothing to watch for {}: ENOENT",
+                    Local::now().format("%d/%m/%Y %H:%M:%S"),
+                    proc_name,
+                    pattern
+                );
+            }
+            for (dir, name) in dirs {
+                match watch.inotify.add_watch(&dir, flags) {
+                    Ok(wd) => watch.dirs.push((wd, name)),
+                    Err(e) => println!(
+                        "{} WARN watch: {} can't watch {}: {}",
+                        Local::now().format("%d/%m/%Y %H:%M:%S"),
+                        proc_name,
+                        dir.display(),
+                        e
+                    ),
+                }
+            }
+        }
+        Some(watch)
+    }
+
+    // name of a watched file that changed since the last call
+    fn read_changes(&self) -> Option<PathBuf> {
+        let events = self.inotify.read_events().unwrap_or_default();
+
+        events.iter().rev().find_map(|event| {
+            let file = event
+                .name
+                .as_ref()
+                .map(|name| name.to_string_lossy().to_string());
+            self.dirs
+                .iter()
+                .filter(|(wd, _)| *wd == event.wd)
+                .find(|(_, name)| match (name, &file) {
+                    (None, _) => true,
+                    (Some(pattern), Some(file)) => glob_match(pattern, file),
+                    (Some(_), None) => false,
+                })
+                .map(|_| PathBuf::from(file.clone().unwrap_or_default()))
+        })
+    }
+}
+
+impl Proc {
+    /// Restart (or signal) the process once its watched files settle.
+    pub fn check_file_watch(&mut self) {
+        let mut watch = match self.file_watch.take() {
+            Some(watch) => watch,
+            None => return,
+        };
+
+        if let Some(file) = watch.read_changes() {
+            watch.changed = Some((Instant::now(), file));
+        }
+        match &watch.changed {
+            Some((since, _)) if since.elapsed() < watch.debounce => {}
+            Some((_, file)) if self.state == State::RUNNING => {
+                let date = Local::now().format("%d/%m/%Y %H:%M:%S");
+                match (watch.signal, self.pid) {
+                    (Some(signal), Some(pid)) => {
+                        println!(
+                            "{} INFO watch: {} changed, sending {} to {}",
+                            date,
+                            file.display(),
+                            signal,
+                            self.get_name()
+                        );
+                        let _ = kill(Pid::from_raw(pid as i32), signal);
+                    }
+                    _ => {
+                        println!(
+                            "{} INFO watch: {} changed, restarting {}",
+                            date,
+                            file.display(),
+                            self.get_name()
+                        );
+                        self.restart_on_stop = true;
+                        let _ = self.stop();
+                    }
+                }
+                watch.changed = None;
+            }
+            // nothing to restart, the next start picks up the change
+            Some(_) => watch.changed = None,
+            None => {}
+        }
+        self.file_watch = Some(watch);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn glob_wildcards() {
+        assert!(glob_match("*.conf", "app.conf"));
+        assert!(glob_match("*.conf", ".conf"));
+        assert!(!glob_match("*.conf", "app.conf.bak"));
+        assert!(glob_match("app?.yml", "app1.yml"));
+        assert!(!glob_match("app?.yml", "app.yml"));
+        assert!(glob_match("*", ""));
+        assert!(glob_match("a*b*c", "aXbYbZc"));
+        assert!(!glob_match("a*b*c", "aXbYbZ"));
+        assert!(glob_match("plain", "plain"));
+        assert!(!glob_match("plain", "plainer"));
+    }
+
+    #[test]
+    fn glob_classes() {
+        assert!(glob_match("log[0-9]", "log7"));
+        assert!(!glob_match("log[0-9]", "logx"));
+        assert!(glob_match("[abc].txt", "b.txt"));
+        assert!(glob_match("[!abc].txt", "d.txt"));
+        assert!(!glob_match("[^abc].txt", "a.txt"));
+        // an unclosed class is a plain '['
+        assert!(glob_match("[ab", "[ab"));
+        assert!(!glob_match("[ab", "a"));
+    }
+}